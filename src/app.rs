@@ -0,0 +1,146 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::*;
+use crate::event::{Sender, Receiver};
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AppEvent {
+    // Inner size of the window in pixels
+    Resized([u32;2]),
+    FocusGained,
+    FocusLost,
+    // The window was resized to nothing, which is how platforms report minimizing
+    Minimized,
+    Restored,
+    // The application was sent to the background, mobile platforms only
+    Suspended,
+    Resumed,
+    // The window is closed after this update unless an element calls AppManager::cancel_close
+    CloseRequested
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Close {
+    Open,
+    Requested,
+    Cancelled,
+    Exit
+}
+
+
+/// Measured frame times in seconds, over the last `FrameStats::WINDOW` frames
+#[derive(Clone, Debug, Default)]
+pub struct FrameStats {
+    pub frames: u64,
+    pub fps: f32,
+    pub frame_time: f32,
+    pub average: f32,
+    pub min: f32,
+    pub max: f32,
+    samples: VecDeque<f32>
+}
+
+impl FrameStats {
+    pub const WINDOW: usize = 120;
+
+    fn record(&mut self, frame_time: f32) {
+        self.frames += 1;
+        self.frame_time = frame_time;
+        if self.samples.len() == Self::WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(frame_time);
+        self.average = self.samples.iter().sum::<f32>() / self.samples.len() as f32;
+        self.min = self.samples.iter().copied().fold(f32::MAX, f32::min);
+        self.max = self.samples.iter().copied().fold(0.0, f32::max);
+        self.fps = if self.average > 0.0 { 1.0 / self.average } else { 0.0 };
+    }
+}
+
+
+/// Shared by the instance and every scene as the "app" module
+#[derive(Clone)]
+pub struct AppManager {
+    sender: Sender<AppEvent>,
+    close: Rc<Cell<Close>>,
+    focused: Rc<Cell<bool>>,
+    stats: Rc<RefCell<FrameStats>>
+}
+
+impl std::default::Default for AppManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AppManager {
+    pub fn new() -> Self {
+        Self {
+            sender: Sender::new(),
+            close: Rc::new(Cell::new(Close::Open)),
+            focused: Rc::new(Cell::new(true)),
+            stats: Rc::new(RefCell::new(FrameStats::default()))
+        }
+    }
+    pub fn new_receiver(&self) -> Rc<Receiver<AppEvent>> {
+        self.sender.new_receiver()
+    }
+    pub fn is_focused(&self) -> bool {
+        self.focused.get()
+    }
+    pub fn frame_stats(&self) -> FrameStats {
+        self.stats.borrow().clone()
+    }
+    /// Keeps the window open after a CloseRequested event
+    pub fn cancel_close(&self) {
+        if self.close.get() == Close::Requested {
+            self.close.set(Close::Cancelled);
+        }
+    }
+    /// Closes the window after this update, without a CloseRequested event
+    pub fn exit(&self) {
+        self.close.set(Close::Exit);
+    }
+
+    pub(crate) fn record_frame(&self, frame_time: f32) {
+        self.stats.borrow_mut().record(frame_time);
+    }
+    // Used by the instance for window events
+    pub(crate) fn send(&self, event: AppEvent) {
+        match event {
+            AppEvent::FocusGained => self.focused.set(true),
+            AppEvent::FocusLost => self.focused.set(false),
+            AppEvent::CloseRequested if self.close.get() != Close::Exit => self.close.set(Close::Requested),
+            _ => ()
+        }
+        self.sender.send(event);
+    }
+    // Whether to close once elements had an update to see the request, resets cancelled requests
+    pub(crate) fn should_close(&self) -> bool {
+        match self.close.get() {
+            Close::Requested | Close::Exit => true,
+            Close::Cancelled => {
+                self.close.set(Close::Open);
+                false
+            },
+            Close::Open => false
+        }
+    }
+}
+
+impl element::ModuleBehavior for AppManager {
+    fn alias(&self) -> String {
+        "app".to_string()
+    }
+    fn component(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+impl element::ElementBehavior for AppManager {
+    fn load(&self, _data: &serde_json::Map<String,serde_json::Value>) -> element::Element {
+        element::Element::new_module(self.clone())
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use serde::{Deserialize,Serialize};
+
+use crate::*;
+
+
+// Neighbor bits of blob masks, 16 tile masks use N = 1, E = 2, S = 4 and W = 8 instead
+pub const N: u8 = 1;
+pub const NE: u8 = 2;
+pub const E: u8 = 4;
+pub const SE: u8 = 8;
+pub const S: u8 = 16;
+pub const SW: u8 = 32;
+pub const W: u8 = 64;
+pub const NW: u8 = 128;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Bitmask {
+    // 47 tiles, corners count only when both neighboring edges match
+    #[serde(rename = "blob")]
+    Blob,
+    // 16 tiles, edges only
+    #[serde(rename = "16")]
+    Sixteen
+}
+
+
+/// Maps the neighbor configuration of a terrain's tiles to sprite indices
+/// Any tile the terrain can produce counts as part of the terrain, so applying rules is repeatable
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Terrain {
+    pub name: String,
+    pub bitmask: Bitmask,
+    // Keyed by neighbor mask
+    #[serde(deserialize_with = "deserialize_rules")]
+    pub rules: HashMap<u8, usize>,
+    // Used for masks without a rule
+    #[serde(deserialize_with = "deserialize_fallback")]
+    pub fallback: Option<usize>
+}
+
+impl std::default::Default for Terrain {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            bitmask: Bitmask::Blob,
+            rules: HashMap::new(),
+            fallback: None
+        }
+    }
+}
+
+impl Terrain {
+    pub fn new(name: &str, bitmask: Bitmask) -> Self {
+        Self {
+            name: name.to_string(),
+            bitmask,
+            ..Default::default()
+        }
+    }
+    pub fn with_rule(mut self, mask: u8, tile: usize) -> Self {
+        self.rules.insert(mask, tile);
+        self
+    }
+    pub fn with_fallback(mut self, tile: usize) -> Self {
+        self.fallback = Some(tile);
+        self
+    }
+    pub fn contains(&self, tile: usize) -> bool {
+        self.fallback == Some(tile) || self.rules.values().any(|t| *t == tile)
+    }
+
+    // `same(dx, dy)` tells whether the neighbor at that offset is part of this terrain, y grows downwards
+    pub fn mask(&self, same: impl Fn(i64, i64) -> bool) -> u8 {
+        let (n, e, s, w) = (same(0,-1), same(1,0), same(0,1), same(-1,0));
+        if self.bitmask == Bitmask::Sixteen {
+            return n as u8 | (e as u8) << 1 | (s as u8) << 2 | (w as u8) << 3
+        }
+        let mut mask = 0;
+        if n { mask |= N; }
+        if e { mask |= E; }
+        if s { mask |= S; }
+        if w { mask |= W; }
+        if n && e && same(1,-1) { mask |= NE; }
+        if s && e && same(1,1) { mask |= SE; }
+        if s && w && same(-1,1) { mask |= SW; }
+        if n && w && same(-1,-1) { mask |= NW; }
+        mask
+    }
+    pub fn tile_for(&self, mask: u8) -> Option<usize> {
+        self.rules.get(&mask).copied().or(self.fallback)
+    }
+}
+
+
+fn terrain_of(terrains: &[Terrain], tile: Option<usize>) -> Option<usize> {
+    tile.and_then(|t| terrains.iter().position(|terrain| terrain.contains(t)))
+}
+
+fn apply_cell(terrains: &[Terrain], tiles: &mut [Option<usize>], width: usize, height: usize, x: usize, y: usize) {
+    let Some(idx) = terrain_of(terrains, tiles[y*width + x]) else {
+        return
+    };
+    let terrain = &terrains[idx];
+    let mask = terrain.mask(|dx, dy| {
+        let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+        if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+            return false
+        }
+        terrain_of(terrains, tiles[ny as usize*width + nx as usize]) == Some(idx)
+    });
+    if let Some(tile) = terrain.tile_for(mask) {
+        tiles[y*width + x] = Some(tile);
+    }
+}
+
+/// Replaces every terrain tile in a row-major grid with the tile matching its neighbors
+pub fn apply(terrains: &[Terrain], tiles: &mut [Option<usize>], width: usize, height: usize) {
+    for y in 0..height {
+        for x in 0..width {
+            apply_cell(terrains, tiles, width, height, x, y);
+        }
+    }
+}
+
+/// Updates a tile and its eight neighbors, used after placing or removing a single tile
+pub fn apply_around(terrains: &[Terrain], tiles: &mut [Option<usize>], width: usize, height: usize, x: usize, y: usize) {
+    for ny in y.saturating_sub(1)..(y+2).min(height) {
+        for nx in x.saturating_sub(1)..(x+2).min(width) {
+            apply_cell(terrains, tiles, width, height, nx, ny);
+        }
+    }
+}
+
+
+
+fn deserialize_rules<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<HashMap<u8, usize>, D::Error> {
+    let named: HashMap<String, sprite::Frame> = HashMap::deserialize(deserializer)?;
+    let mut rules = HashMap::new();
+    for (mask, frame) in named {
+        let mask = mask.parse::<u8>().map_err(|_| serde::de::Error::custom(format!("Invalid neighbor mask \"{}\"", mask)))?;
+        rules.insert(mask, frame.resolve().map_err(serde::de::Error::custom)?);
+    }
+    Ok(rules)
+}
+
+fn deserialize_fallback<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<usize>, D::Error> {
+    match Option::<sprite::Frame>::deserialize(deserializer)? {
+        Some(frame) => Ok(Some(frame.resolve().map_err(serde::de::Error::custom)?)),
+        None => Ok(None)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Neighbors at the given offsets are part of the terrain
+    fn same(offsets: &[(i64, i64)]) -> impl Fn(i64, i64) -> bool + '_ {
+        move |dx, dy| offsets.contains(&(dx, dy))
+    }
+
+    // Tile 100 + mask for every 16 tile mask
+    fn sixteen() -> Terrain {
+        (0..16).fold(Terrain::new("path", Bitmask::Sixteen), |t, mask| t.with_rule(mask, 100 + mask as usize))
+    }
+
+    #[test]
+    fn computes_sixteen_tile_masks() {
+        let terrain = sixteen();
+        assert_eq!(terrain.mask(same(&[])), 0);
+        assert_eq!(terrain.mask(same(&[(0,-1), (-1,0)])), 1 | 8);
+        // Corners don't count
+        assert_eq!(terrain.mask(same(&[(0,1), (1,1), (1,0), (-1,-1)])), 2 | 4);
+    }
+
+    #[test]
+    fn counts_blob_corners_between_matching_edges() {
+        let terrain = Terrain::new("grass", Bitmask::Blob);
+        let all = [(0,-1), (1,-1), (1,0), (1,1), (0,1), (-1,1), (-1,0), (-1,-1)];
+        assert_eq!(terrain.mask(same(&all)), 255);
+        assert_eq!(terrain.mask(same(&[(0,-1), (1,0), (1,-1)])), N | E | NE);
+        // Without both edges the corner is left out
+        assert_eq!(terrain.mask(same(&[(0,-1), (1,-1)])), N);
+        assert_eq!(terrain.mask(same(&[(1,1), (-1,1), (-1,-1), (1,-1)])), 0);
+        assert_eq!(terrain.mask(same(&[(0,1), (-1,0), (-1,1), (1,1)])), S | W | SW);
+    }
+
+    #[test]
+    fn resolves_frame_names_in_rules() {
+        let frames = std::rc::Rc::new(HashMap::from([("island".to_string(), 12), ("grass".to_string(), 20)]));
+        let data = serde_json::json!({"name": "grass", "bitmask": "16", "rules": {"0": "island", "15": 21}, "fallback": "grass"});
+        let terrain = sprite::with_frames(&frames, || serde_json::from_value::<Terrain>(data)).unwrap();
+        assert_eq!(terrain.bitmask, Bitmask::Sixteen);
+        assert_eq!((terrain.tile_for(0), terrain.tile_for(15), terrain.tile_for(3)), (Some(12), Some(21), Some(20)));
+        assert!(terrain.contains(20) && terrain.contains(12) && !terrain.contains(13));
+
+        let unknown = serde_json::json!({"rules": {"0": "lava"}});
+        assert!(sprite::with_frames(&frames, || serde_json::from_value::<Terrain>(unknown)).is_err());
+        let bad_mask = serde_json::json!({"rules": {"north": 1}});
+        assert!(sprite::with_frames(&frames, || serde_json::from_value::<Terrain>(bad_mask)).is_err());
+    }
+
+    #[test]
+    fn treats_map_edges_as_other_terrain() {
+        let terrains = [sixteen()];
+        let mut tiles = vec![None; 9];
+        tiles[0] = Some(100);
+        tiles[1] = Some(100);
+        apply_around(&terrains, &mut tiles, 3, 3, 0, 0);
+        assert_eq!(tiles[..2], [Some(102), Some(108)]);
+
+        // Bottom right corner, only cells inside the map are touched
+        tiles[8] = Some(100);
+        apply_around(&terrains, &mut tiles, 3, 3, 2, 2);
+        assert_eq!(tiles, vec![Some(102), Some(108), None, None, None, None, None, None, Some(100)]);
+
+        let mut tiles = vec![Some(100); 9];
+        apply(&terrains, &mut tiles, 3, 3);
+        assert_eq!((tiles[0], tiles[4], tiles[8]), (Some(102 + 4), Some(115), Some(101 + 8)));
+    }
+}
@@ -1,53 +1,409 @@
+use std::rc::Rc;
+
 use crate::*;
 use element::*;
 
+
+#[derive(Clone)]
+pub enum CameraEvent {
+    Follow(Option<Uuid>),
+    FollowTag(Option<String>),
+    SetBounds(Option<[f32;4]>),
+    MoveTo([f32;2]),
+    Shake(Shake),
+    // Zooms in by `amount` (0.1 is 10%) at once and eases back out over `duration` seconds
+    Punch { amount: f32, duration: f32 },
+    // Eases the scale to `scale` over `duration` seconds, keeping `point` at the same place on screen when set
+    ZoomTo { scale: f32, point: Option<[f32;2]>, duration: f32 },
+    StopEffects
+}
+
+
+/// Trauma based screen shake, the shake is as strong as trauma squared
+/// Trauma adds up to at most 1 and decays to 0 over `duration` seconds
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Shake {
+    pub trauma: f32,
+    // Largest offset in world units
+    pub amplitude: f32,
+    // Shakes per second
+    pub frequency: f32,
+    pub duration: f32
+}
+
+impl std::default::Default for Shake {
+	fn default() -> Self {
+		Self {
+			trauma: 0.5,
+			amplitude: 0.5,
+			frequency: 15.0,
+			duration: 0.5
+		}
+	}
+}
+
+
+#[derive(Clone, Default)]
+struct Effects {
+	trauma: f32,
+	shake: Shake,
+	time: f32,
+	shake_offset: [f32;2],
+	// Amount, duration and elapsed time
+	punch: Option<(f32, f32, f32)>,
+	zoom: Option<Zoom>
+}
+
+#[derive(Clone)]
+struct Zoom {
+	from: f32,
+	to: f32,
+	point: Option<[f32;2]>,
+	duration: f32,
+	elapsed: f32
+}
+
+
+/// Keeps a target element in view, its position comes from ElementBehavior::position
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(default)]
+pub struct Follow {
+    #[serde(skip)]
+    pub target: Option<Uuid>,
+    // Used to find the target when none is set, the first element with the tag is followed
+    pub tag: Option<String>,
+    // Added to the target position
+    pub offset: [f32;2],
+    // Width and height of the area around the center the target can move in without moving the camera
+    pub deadzone: [f32;2],
+    // Seconds of the target's velocity the camera looks ahead by
+    pub look_ahead: [f32;2],
+    // How quickly the camera catches up, per second, 0 moves it immediately
+    pub smoothing: f32
+}
+
+impl std::default::Default for Follow {
+	fn default() -> Self {
+		Self {
+			target: None,
+			tag: None,
+			offset: [0.0,0.0],
+			deadzone: [0.0,0.0],
+			look_ahead: [0.0,0.0],
+			smoothing: 0.0
+		}
+	}
+}
+
+
 #[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct DefaultCamera {
 	pub pos: [f32;2],
 	pub scale: f32,
-	aspect: f32
+	aspect: f32,
+	// Module alias CameraEvents are sent to
+	pub id: String,
+	pub follow: Option<Follow>,
+	// Left, bottom, right and top edges the view is kept inside of
+	pub bounds: Option<[f32;4]>,
+	pub viewport: render::Viewport,
+	// Rounds the offset to whole pixels of the viewport so pixel art doesn't shimmer while moving,
+	// layers with a parallax other than 1 are not snapped
+	pub pixel_snap: bool,
+
+	#[serde(skip)]
+	last_target: Option<V2<f32>>,
+	#[serde(skip)]
+	td: f32,
+	#[serde(skip)]
+	effects: Effects,
+	#[serde(skip)]
+	positions: scene::Positions,
+	#[serde(skip)]
+	tags: scene::UuidTags,
+	#[serde(skip)]
+	screen: scene::Screen,
+	#[serde(skip)]
+	sender: event::Sender<CameraEvent>,
+	#[serde(skip)]
+	receiver: Rc<event::Receiver<CameraEvent>>
 }
 
 impl std::default::Default for DefaultCamera {
 	fn default() -> Self {
+		let sender = event::Sender::new();
 		Self {
 			pos: [0.0,0.0],
 			scale: 20.0,
-			aspect: 16./9.
+			aspect: 16./9.,
+			id: "camera".to_string(),
+			follow: None,
+			bounds: None,
+			viewport: render::Viewport::default(),
+			pixel_snap: false,
+
+			last_target: None,
+			td: 0.0,
+			effects: Effects::default(),
+			positions: scene::Positions::default(),
+			tags: scene::UuidTags::default(),
+			screen: scene::Screen::default(),
+			receiver: sender.new_receiver(),
+			sender
 		}
 	}
 }
 
+impl DefaultCamera {
+	pub fn with_follow(mut self, follow: Follow) -> Self {
+		self.follow = Some(follow);
+		self
+	}
+	pub fn with_bounds(mut self, bounds: [f32;4]) -> Self {
+		self.bounds = Some(bounds);
+		self
+	}
+	pub fn with_viewport(mut self, viewport: render::Viewport) -> Self {
+		self.viewport = viewport;
+		self
+	}
+
+	// Width and height of the visible area in world units, without effects
+	pub fn view_size(&self, window_size: [u32;2]) -> [f32;2] {
+		self.view_size_at(self.scale, window_size)
+	}
+	fn view_size_at(&self, scale: f32, window_size: [u32;2]) -> [f32;2] {
+		let aspect_ratio = window_size[0] as f32/window_size[1].max(1) as f32;
+		let mut width = scale;
+		let mut height = scale/self.aspect;
+		if aspect_ratio > self.aspect { // more width
+			width = height * aspect_ratio;
+		} else if aspect_ratio < self.aspect {
+			height = width / aspect_ratio;
+		}
+		[width, height]
+	}
+
+	fn target_position(&self, follow: &Follow) -> Option<V2<f32>> {
+		if let Some(target) = follow.target {
+			return self.positions.get(&target)
+		}
+		let tag = follow.tag.as_ref()?;
+		self.tags.with_tag(tag).iter().find_map(|uuid| self.positions.get(uuid))
+	}
+
+	fn update_follow(&mut self) {
+		let Some(follow) = self.follow.clone() else {
+			return;
+		};
+		let Some(target) = self.target_position(&follow) else {
+			self.last_target = None;
+			return;
+		};
+
+		let velocity = match self.last_target {
+			Some(last) if self.td > 0.0 => [(target.x - last.x)/self.td, (target.y - last.y)/self.td],
+			_ => [0.0,0.0]
+		};
+		self.last_target = Some(target);
+
+		let mut goal = self.pos;
+		for axis in 0..2 {
+			let desired = [target.x, target.y][axis] + follow.offset[axis] + velocity[axis]*follow.look_ahead[axis];
+			let half = follow.deadzone[axis]*0.5;
+			let delta = desired - self.pos[axis];
+			if delta > half {
+				goal[axis] = desired - half;
+			} else if delta < -half {
+				goal[axis] = desired + half;
+			}
+		}
+
+		// Exponential smoothing, independent of the frame rate
+		let t = if follow.smoothing > 0.0 { 1.0 - (-follow.smoothing*self.td).exp() } else { 1.0 };
+		self.pos = [self.pos[0] + (goal[0] - self.pos[0])*t, self.pos[1] + (goal[1] - self.pos[1])*t];
+	}
+
+	pub fn trauma(&self) -> f32 {
+		self.effects.trauma
+	}
+	// Scale including the punch zoom
+	pub fn effective_scale(&self) -> f32 {
+		match self.effects.punch {
+			Some((amount, duration, elapsed)) if duration > 0.0 => {
+				let k = (1.0 - elapsed/duration).max(0.0);
+				self.scale / (1.0 + amount*k*k)
+			},
+			_ => self.scale
+		}
+	}
+
+	fn update_zoom(&mut self) {
+		let Some(zoom) = self.effects.zoom.as_mut() else {
+			return;
+		};
+		zoom.elapsed += self.td;
+		let t = if zoom.duration > 0.0 { (zoom.elapsed/zoom.duration).min(1.0) } else { 1.0 };
+		// Smoothstep
+		let eased = t*t*(3.0 - 2.0*t);
+		let scale = zoom.from + (zoom.to - zoom.from)*eased;
+		if let Some(point) = zoom.point {
+			let ratio = scale/self.scale;
+			self.pos = [point[0] + (self.pos[0] - point[0])*ratio, point[1] + (self.pos[1] - point[1])*ratio];
+		}
+		self.scale = scale;
+		if t >= 1.0 {
+			self.effects.zoom = None;
+		}
+	}
+
+	fn update_effects(&mut self) {
+		let td = self.td;
+		let effects = &mut self.effects;
+		effects.time += td;
+
+		if let Some((_, duration, elapsed)) = effects.punch.as_mut() {
+			*elapsed += td;
+			if *elapsed >= *duration {
+				effects.punch = None;
+			}
+		}
+
+		if effects.shake.duration > 0.0 {
+			effects.trauma = (effects.trauma - td/effects.shake.duration).max(0.0);
+		} else {
+			effects.trauma = 0.0;
+		}
+		let strength = effects.trauma*effects.trauma*effects.shake.amplitude;
+		let t = effects.time*effects.shake.frequency*std::f32::consts::TAU;
+		effects.shake_offset = [strength*noise(t, 0.0), strength*noise(t, 31.7)];
+	}
+
+	fn clamp_to_bounds(&mut self) {
+		let Some([left, bottom, right, top]) = self.bounds else {
+			return;
+		};
+		let [_, _, width, height] = self.viewport.pixel_rect(self.screen.size());
+		if width == 0 || height == 0 {
+			return;
+		}
+		let view = self.view_size([width, height]);
+		let clamp = |pos: f32, min: f32, max: f32, half: f32| {
+			// Bounds smaller than the view keep it centered on them
+			if max - min < half*2.0 { (min + max)*0.5 } else { pos.clamp(min + half, max - half) }
+		};
+		self.pos = [clamp(self.pos[0], left, right, view[0]*0.5), clamp(self.pos[1], bottom, top, view[1]*0.5)];
+	}
+}
+
 
 
 impl ElementBehavior for DefaultCamera {
     fn init(&mut self, uuid: uuid::Uuid, mods: &ModuleTool) {
         mods.access("scene broadcast", |scene: &event::Sender<scene::SceneEvent> | {
-            scene.send(scene::SceneEvent::SetCamera(uuid))
+            scene.send(scene::SceneEvent::AddCamera(uuid))
         });
+        mods.access("positions", |positions: &scene::Positions| {
+            self.positions = positions.clone();
+        });
+        mods.access("uuid tags", |tags: &scene::UuidTags| {
+            self.tags = tags.clone();
+        });
+        mods.access("screen", |screen: &scene::Screen| {
+            self.screen = screen.clone();
+        });
+	}
+	fn local_update(&mut self, td: f32) {
+		self.td = td;
+		for e in self.receiver.poll() {
+			match e {
+				CameraEvent::Follow(target) => {
+					self.follow.get_or_insert_with(Follow::default).target = target;
+					self.last_target = None;
+				},
+				CameraEvent::FollowTag(tag) => {
+					self.follow.get_or_insert_with(Follow::default).tag = tag;
+					self.last_target = None;
+				},
+				CameraEvent::SetBounds(bounds) => self.bounds = bounds,
+				CameraEvent::MoveTo(pos) => self.pos = pos,
+				CameraEvent::Shake(shake) => {
+					let effects = &mut self.effects;
+					// A weaker shake doesn't cut a running one short
+					let amplitude = if effects.trauma > 0.0 { shake.amplitude.max(effects.shake.amplitude) } else { shake.amplitude };
+					effects.trauma = (effects.trauma + shake.trauma).min(1.0);
+					effects.shake = Shake { amplitude, ..shake };
+				},
+				CameraEvent::Punch { amount, duration } => self.effects.punch = Some((amount, duration, 0.0)),
+				CameraEvent::ZoomTo { scale, point, duration } => self.effects.zoom = Some(Zoom {
+					from: self.scale,
+					to: scale,
+					point,
+					duration,
+					elapsed: 0.0
+				}),
+				CameraEvent::StopEffects => {
+					self.effects.trauma = 0.0;
+					self.effects.shake_offset = [0.0,0.0];
+					self.effects.punch = None;
+					self.effects.zoom = None;
+				}
+			}
+		}
+	}
+	// Targets have moved during local_update
+	fn post_update(&mut self) {
+		self.update_zoom();
+		self.update_follow();
+		self.clamp_to_bounds();
+		self.update_effects();
 	}
 	fn load(&self, data: &serde_json::Map<String, serde_json::Value>) -> Element {
-        if let Some(settings) = data.get("settings") {
-            element::Element::new_gen(serde_json::from_value::<DefaultCamera>(settings.clone()).unwrap())
-        } else {
-            element::Element::new_gen(Self::default())
-        }
+		Element::or_print(self.try_load(data))
 	}
-    fn clip_matrix(&self, window_size: [u32;2]) -> [[f32;3];3] {
-		let aspect_ratio = window_size[0] as f32/window_size[1] as f32;
-		let mut width = self.scale;
-		let mut height = self.scale/self.aspect;
-		if aspect_ratio > self.aspect { // more width
-			width = height * aspect_ratio;
-		} else if aspect_ratio < self.aspect {
-			height = width / aspect_ratio;
+	fn try_load(&self, data: &serde_json::Map<String, serde_json::Value>) -> Result<Element,String> {
+		match data.get("settings") {
+			Some(settings) => serde_json::from_value::<DefaultCamera>(settings.clone())
+				.map(element::Element::new_module)
+				.map_err(|e| format!("Failed to load camera: {}", e)),
+			None => Ok(element::Element::new_module(Self::default()))
 		}
+	}
+    fn clip_matrix(&self, window_size: [u32;2]) -> [[f32;3];3] {
+		let [width, height] = self.view_size_at(self.effective_scale(), window_size);
 		[[2.0/width, 0.0, 0.0],
 		[0.0, 2.0/height, 0.0],
 		[0.0, 0.0, 1.0f32]]
 	}
+	fn viewport(&self) -> render::Viewport {
+		self.viewport.clone()
+	}
+	// Shake is applied on top of the followed position
 	fn offset(&self) -> [f32;2] {
-		self.pos
+		let offset = [self.pos[0] + self.effects.shake_offset[0], self.pos[1] + self.effects.shake_offset[1]];
+		let [_, _, width, height] = self.viewport.pixel_rect(self.screen.size());
+		if !self.pixel_snap || width == 0 || height == 0 {
+			return offset
+		}
+		let view = self.view_size_at(self.effective_scale(), [width, height]);
+		let texel = [view[0]/width as f32, view[1]/height as f32];
+		[(offset[0]/texel[0]).round()*texel[0], (offset[1]/texel[1]).round()*texel[1]]
+	}
+}
+
+impl ModuleBehavior for DefaultCamera {
+	fn alias(&self) -> String {
+		self.id.clone()
 	}
-}
\ No newline at end of file
+	fn component(&self) -> &dyn std::any::Any {
+		&self.sender
+	}
+}
+
+
+// Smooth noise from -1 to 1, sines of unrelated frequencies so the shake doesn't visibly repeat
+fn noise(t: f32, seed: f32) -> f32 {
+	((t*1.0 + seed).sin()*0.5 + (t*2.31 + seed*1.7).sin()*0.3 + (t*4.77 + seed*2.9).sin()*0.2).clamp(-1.0, 1.0)
+}
@@ -10,6 +10,12 @@ enum MapEditorState {
     Zoom,
     ElementSelected {
         //sliders: HashMap<String, Vec<[f32;2]>>
+    },
+    // Painting tiles of the current element when it is a tilemap
+    // The tilemap is written back to the current element when leaving the state
+    Tile {
+        cursor: (usize, usize),
+        tilemap: Box<super::tilemap::Tilemap>
     }
 }
 
@@ -17,6 +23,8 @@ enum MapEditorState {
 pub struct MapEditor {
     scene: event::Sender<scene::SceneEvent>,
     input_receiver: Rc<event::Receiver<instance::InputEvent>>,
+    input: instance::InputManager,
+    actions: input::ActionMap,
 
     placed_elements: Vec<Element>,
 
@@ -25,6 +33,7 @@ pub struct MapEditor {
     json_manager: std::rc::Weak<JSONManager>,
     order: Vec<String>,
     idx: usize,
+    tile: usize,
 
     current_element: Option<serde_json::Map<String,serde_json::Value>>,
 
@@ -38,6 +47,8 @@ impl MapEditor {
         Self {
             scene: event::Sender::new(),
             input_receiver: event::Receiver::new(),
+            input: instance::InputManager::new(),
+            actions: input::ActionMap::new(Self::default_bindings()),
 
             placed_elements: Vec::new(),
             current_element: None,
@@ -46,31 +57,76 @@ impl MapEditor {
             json_manager: std::rc::Weak::new(),
             order: Vec::new(),
             idx: 0,
+            tile: 0,
 
             camera: DefaultCamera::default(),
             
             state: MapEditorState::Pan
         }
     }
+    pub fn with_bindings(mut self, bindings: input::Bindings) -> Self {
+        self.actions = input::ActionMap::new(bindings);
+        self
+    }
+    pub fn default_bindings() -> input::Bindings {
+        use input::{Binding, GamepadButton as G, VirtualKeyCode as K};
+        let key = |k: K| Binding::key(k);
+        input::Bindings::new()
+            .with_action("pan", vec![key(K::H)])
+            .with_action("zoom", vec![key(K::Z)])
+            .with_action("select", vec![key(K::E)])
+            .with_action("tile", vec![key(K::T)])
+            .with_action("print", vec![key(K::Space)])
+            .with_action("up", vec![key(K::Up), Binding::GamepadButton(G::DPadUp)])
+            .with_action("down", vec![key(K::Down), Binding::GamepadButton(G::DPadDown)])
+            .with_action("left", vec![key(K::Left), Binding::GamepadButton(G::DPadLeft)])
+            .with_action("right", vec![key(K::Right), Binding::GamepadButton(G::DPadRight)])
+            .with_action("shape_up", vec![key(K::W)])
+            .with_action("shape_down", vec![key(K::S)])
+            .with_action("shape_left", vec![key(K::A)])
+            .with_action("shape_right", vec![key(K::D)])
+            .with_action("next_element", vec![key(K::R)])
+            .with_action("place", vec![key(K::C), Binding::GamepadButton(G::South)])
+            .with_action("next_tile", vec![key(K::N)])
+            .with_action("paint", vec![key(K::P), Binding::GamepadButton(G::South)])
+            .with_action("erase", vec![key(K::X), Binding::GamepadButton(G::East)])
+    }
+    fn tile_state(&self) -> Result<MapEditorState,String> {
+        let json = self.json_manager.upgrade().ok_or("No JSONManager to load the tilemap with")?;
+        let settings = self.current_element.as_ref().and_then(|e| e.get("settings")).ok_or("The current element has no settings")?;
+        let tilemap = json.from_value::<super::tilemap::Tilemap>(settings)?;
+        tilemap.check_size()?;
+        if tilemap.width == 0 || tilemap.height == 0 {
+            return Err("The current element is not a tilemap".to_string())
+        }
+        Ok(MapEditorState::Tile { cursor: (0,0), tilemap: Box::new(tilemap) })
+    }
+    fn set_state(&mut self, state: MapEditorState) {
+        self.store_tilemap();
+        self.state = state;
+    }
+    // Writes the tilemap being painted back to the current element
+    fn store_tilemap(&mut self) {
+        if let (MapEditorState::Tile { tilemap, .. }, Some(e)) = (&self.state, self.current_element.as_mut()) {
+            if let Ok(settings) = serde_json::to_value(tilemap.as_ref()) {
+                e.insert("settings".to_string(), settings);
+            }
+        }
+    }
+    // Cycles through terrains when there are any, otherwise through sprite indices
+    // Terrains without a fallback paint their lowest mask variant, autotiling picks the right one
+    fn selected_tile(idx: usize, terrains: &[super::autotile::Terrain]) -> usize {
+        let tiles: Vec<usize> = terrains.iter()
+            .filter_map(|t| t.fallback.or_else(|| t.rules.iter().min_by_key(|(mask, _)| **mask).map(|(_, tile)| *tile)))
+            .collect();
+        if tiles.is_empty() {
+            idx
+        } else {
+            tiles[idx % tiles.len()]
+        }
+    }
 }
 
-const UP: u32 = 72;
-const LEFT: u32 = 75;
-const DOWN: u32 = 80;
-const RIGHT: u32 = 77;
-
-const H: u32 = 35;
-const Z: u32 = 44;
-const R: u32 = 19;
-const E: u32  = 18;
-const C: u32 = 46;
-
-
-const W: u32 = 17;
-const A: u32 = 30;
-const S: u32 = 31;
-const D: u32 = 32;
-
 impl element::ElementBehavior for MapEditor {
     fn clip_matrix(&self, window_size: [u32;2]) -> [[f32;3];3] {
         self.camera.clip_matrix(window_size)
@@ -84,9 +140,10 @@ impl element::ElementBehavior for MapEditor {
             self.scene = scene.clone();
             self.scene_receiver = scene.new_receiver();
         }); 
-        mods.access("input", |input: &event::Sender<instance::InputEvent, event::Locked>| {
+        if let Some(input) = mods.input() {
             self.input_receiver = input.new_receiver();
-        });
+            self.input = input;
+        }
     }
     fn local_update(&mut self, td: f32) {
         for e in self.scene_receiver.poll() {
@@ -101,58 +158,57 @@ impl element::ElementBehavior for MapEditor {
             }
         }
         let mut key_down = Vec::new();
+        let gamepads = self.input.gamepads();
         for e in self.input_receiver.poll() {
-            match e {
-                InputEvent::KeyDown(scancode) => match scancode {
-                    Z => self.state = MapEditorState::Zoom,
-                    H => self.state = MapEditorState::Pan,
-                    E => self.state = MapEditorState::ElementSelected {},
-                    57 => println!("{}",serde_json::to_string_pretty(&self.save()).unwrap()),
-                    _ => key_down.push(scancode),
-                },
-                InputEvent::KeyUp(..) => ()
+            // Scrolling zooms in any state
+            if let InputEvent::Scroll(delta) = e {
+                self.camera.scale = (self.camera.scale - delta.y * 0.5).max(0.5);
+            }
+            for action in self.actions.update(&e, &gamepads) {
+                let input::ActionEvent::Pressed { action, .. } = action else {
+                    continue;
+                };
+                match action.as_str() {
+                    "zoom" => self.set_state(MapEditorState::Zoom),
+                    "pan" => self.set_state(MapEditorState::Pan),
+                    "select" => self.set_state(MapEditorState::ElementSelected {}),
+                    "tile" => match self.tile_state() {
+                        Ok(state) => self.set_state(state),
+                        Err(e) => println!("Can't paint tiles: {}", e)
+                    },
+                    "print" => {
+                        self.store_tilemap();
+                        println!("{}",serde_json::to_string_pretty(&self.save()).unwrap())
+                    },
+                    _ => key_down.push(action),
+                }
             }
         }
+        // Held directions, so movement doesn't depend on key repeat
+        let axis = |negative: &str, positive: &str| self.actions.any_pressed(positive) as i32 as f32 - self.actions.any_pressed(negative) as i32 as f32;
+        let dir = V2 { x: axis("left", "right"), y: axis("down", "up") };
+        let shape_dir = V2 { x: axis("shape_left", "shape_right"), y: axis("shape_down", "shape_up") };
+
         match self.state {
             MapEditorState::Pan => {
-                let speed = 0.5;
-                for k in key_down {
-                    match k {
-                        UP => self.camera.pos[1] += speed,
-                        DOWN => self.camera.pos[1] += -speed,
-                        LEFT => self.camera.pos[0] += -speed,
-                        RIGHT => self.camera.pos[0] += speed,
-                        _ => ()
-                    }
-                }
+                let speed = 15.0 * td;
+                self.camera.pos[0] += dir.x * speed;
+                self.camera.pos[1] += dir.y * speed;
             },
             MapEditorState::Zoom => {
-                let speed = 0.5;
-                for k in key_down {
-                    match k {
-                        UP => self.camera.scale += -speed,
-                        DOWN => self.camera.scale += speed,
-                        _ => ()
-                    }
-                }
+                let speed = 15.0 * td;
+                self.camera.scale = (self.camera.scale - dir.y * speed).max(0.5);
             },
             MapEditorState::ElementSelected {} => {
-                let speed = 0.1;
+                let speed = 3.0 * td;
                 if let Some(e) = &mut self.current_element {
                     if let Some(serde_json::Value::Array(pos)) = e.get("pos") {
                         let mut pos_v2 = V2 {
                             x: pos[0].as_number().unwrap().as_f64().unwrap() as f32,
                             y: pos[1].as_number().unwrap().as_f64().unwrap() as f32,
                         };
-                        for k in &key_down {
-                            match *k {
-                                UP => pos_v2.y += speed,
-                                DOWN => pos_v2.y += -speed,
-                                LEFT => pos_v2.x += -speed,
-                                RIGHT => pos_v2.x += speed,
-                                _ => ()
-                            }
-                        }
+                        pos_v2.x += dir.x * speed;
+                        pos_v2.y += dir.y * speed;
                         e["pos"] = serde_json::to_value([pos_v2.x,pos_v2.y]).unwrap();
                     } else if let Some(serde_json::Value::Object(settings)) = e.get("settings") {
                         if let Some(serde_json::Value::Array(pos)) = settings.get("pos") {
@@ -160,15 +216,8 @@ impl element::ElementBehavior for MapEditor {
                                 x: pos[0].as_number().unwrap().as_f64().unwrap() as f32,
                                 y: pos[1].as_number().unwrap().as_f64().unwrap() as f32,
                             };
-                            for k in &key_down {
-                                match *k {
-                                    UP => pos_v2.y += speed,
-                                    DOWN => pos_v2.y += -speed,
-                                    LEFT => pos_v2.x += -speed,
-                                    RIGHT => pos_v2.x += speed,
-                                    _ => ()
-                                }
-                            }
+                            pos_v2.x += dir.x * speed;
+                            pos_v2.y += dir.y * speed;
                             e["pos"] = serde_json::to_value([pos_v2.x,pos_v2.y]).unwrap();
                         }
                     }
@@ -180,23 +229,16 @@ impl element::ElementBehavior for MapEditor {
                             x: pos[0].as_number().unwrap().as_f64().unwrap() as f32,
                             y: pos[1].as_number().unwrap().as_f64().unwrap() as f32,
                         };
-                        for k in &key_down {
-                            match *k {
-                                W => pos_v2.y += speed,
-                                S => pos_v2.y += -speed,
-                                A => pos_v2.x += -speed,
-                                D => pos_v2.x += speed,
-                                _ => ()
-                            }
-                        }
+                        pos_v2.x += shape_dir.x * speed;
+                        pos_v2.y += shape_dir.y * speed;
                         e["shape"] = serde_json::to_value([pos_v2.x,pos_v2.y]).unwrap();
                     }
                 }
 
 
                 for k in &key_down {
-                    match *k {
-                        R => {
+                    match k.as_str() {
+                        "next_element" => {
                             if let Some(json) = self.json_manager.upgrade() {
                                 println!("{:?}", self.order);
                                 self.idx += 1;
@@ -211,10 +253,13 @@ impl element::ElementBehavior for MapEditor {
                                 }
                             }
                         },
-                        C => {
+                        "place" => {
                             if let Some(json) = self.json_manager.upgrade() {
                                 if let Some(e) =& self.current_element {
-                                    self.placed_elements.push(json.create_element(&serde_json::Value::Object(e.clone())));
+                                    match json.create_element(&serde_json::Value::Object(e.clone())) {
+                                        Ok(element) => self.placed_elements.push(element),
+                                        Err(e) => println!("Failed to place: {}", e)
+                                    }
                                 }
                             }
                         }
@@ -222,7 +267,43 @@ impl element::ElementBehavior for MapEditor {
                     }
                 }
             }
-            _ => ()
+            MapEditorState::Tile { ref mut cursor, ref mut tilemap } => {
+                let json = self.json_manager.upgrade();
+                let terrains = json.as_ref().map_or(&[][..], |j| &j.terrains[..]);
+
+                for k in &key_down {
+                    match k.as_str() {
+                        "up" => cursor.1 = cursor.1.saturating_sub(1),
+                        "down" => cursor.1 = (cursor.1 + 1).min(tilemap.height.saturating_sub(1)),
+                        "left" => cursor.0 = cursor.0.saturating_sub(1),
+                        "right" => cursor.0 = (cursor.0 + 1).min(tilemap.width.saturating_sub(1)),
+                        "next_tile" => self.tile += 1,
+                        "paint" | "erase" => {
+                            let tile = if k == "erase" { None } else { Some(Self::selected_tile(self.tile, terrains)) };
+                            if tilemap.autotile {
+                                tilemap.set_tile_autotiled(cursor.0, cursor.1, tile, terrains);
+                            } else {
+                                tilemap.set_tile(cursor.0, cursor.1, tile);
+                            }
+                        },
+                        _ => ()
+                    }
+                }
+                // Left button paints and right button erases the tile under the cursor
+                let mouse = self.input.cursor();
+                let painting = [(instance::MouseButton::Left, Some(Self::selected_tile(self.tile, terrains))), (instance::MouseButton::Right, None)]
+                    .into_iter().find(|(b, _)| mouse.is_held(*b));
+                if let (Some((_, tile)), Some((x, y))) = (painting, mouse.world.and_then(|w| tilemap.tile_at(w))) {
+                    *cursor = (x, y);
+                    if tilemap.tile(x, y) != tile {
+                        if tilemap.autotile {
+                            tilemap.set_tile_autotiled(x, y, tile, terrains);
+                        } else {
+                            tilemap.set_tile(x, y, tile);
+                        }
+                    }
+                }
+            }
 
 
 
@@ -249,19 +330,26 @@ impl element::ElementBehavior for MapEditor {
     fn load(&self, data: &serde_json::Map<String,serde_json::Value>) -> Element {
         Element::new_gen(Self::new())
     }
-    fn sprite(&self) -> Option<super::sprite::Sprite> {
-        let mut sprite = sprite::Sprite::empty();
-        for e in &self.placed_elements {
-            if let Some(s) = e.sprite() {
-                sprite.next(s);
-            }
-        }
-        if let Some(e) = &self.current_element {
-            if let Some(json) = self.json_manager.upgrade() {
-                sprite.next(json.create_element(&serde_json::Value::Object(e.clone())).sprite().unwrap())
+    // Placed elements may bake their own vertices, like tilemaps, so they're drawn through draw_items
+    fn draw_items(&self, sprite_sheet: &sprite::SpriteSheet, layers: &[render::RenderLayer], order: usize, visible: &render::VisibleArea) -> Option<Vec<render::DrawItem>> {
+        let mut items: Vec<render::DrawItem> = self.placed_elements.iter().flat_map(|e| e.draw_items(sprite_sheet, layers, order, visible)).collect();
+        let json = self.json_manager.upgrade();
+        if let MapEditorState::Tile { cursor, tilemap } = &self.state {
+            items.extend(tilemap.draw_items(sprite_sheet, layers, order, visible).unwrap_or_default());
+            // Highlight the tile being painted
+            let terrains = json.as_ref().map_or(&[][..], |j| &j.terrains[..]);
+            let highlight = sprite::Sprite::single(Self::selected_tile(self.tile, terrains))
+                .with_pos(tilemap.tile_pos(cursor.0, cursor.1))
+                .with_scale(tilemap.tile_size)
+                .with_layer("foreground")
+                .translucent(true);
+            render::sprite_items(highlight, sprite_sheet, layers, order, &mut items);
+        } else if let (Some(e), Some(json)) = (&self.current_element, &json) {
+            if let Ok(element) = json.create_element(&serde_json::Value::Object(e.clone())) {
+                items.extend(element.draw_items(sprite_sheet, layers, order, visible));
             }
         }
 
-        Some(sprite)
+        Some(items)
     }
 }
\ No newline at end of file
@@ -2,6 +2,10 @@ pub mod physics;
 pub mod particles;
 pub mod camera;
 pub mod map_editor;
+pub mod tilemap;
+pub mod autotile;
+pub mod text;
+pub mod ui;
 
 
 
@@ -36,10 +40,13 @@ impl Block {
         Self {
             pos: pos,
             shape: shape,
-            sprite: sprite::Sprite { pos: pos, scale: shape, depth: 0.0, tex_indices: vec![vec![index]], next: None, flip: false },
+            sprite: sprite::Sprite::single(index).with_pos(pos).with_scale(shape).with_depth(0.0),
             ..Default::default()
         }
     }
+    pub fn new_named(pos: V2<f32>, shape: V2<f32>, sprite_sheet: &sprite::SpriteSheet, frame: &str) -> Result<Self,String> {
+        Ok(Self::new_stretched(pos, shape, sprite_sheet.frame(frame)?))
+    }
     pub fn with_depth(mut self, depth: f32) -> Self {
         self.sprite.depth = depth;
         self
@@ -75,17 +82,23 @@ impl element::ElementBehavior for Block {
         });
     }
     fn load(&self, data: &serde_json::Map<String,serde_json::Value>) -> element::Element {
-        if let Some(s) = data.get("settings") {
-            element::Element::new_gen(serde_json::from_value::<Block>(s.clone()).unwrap())
-        } else {
-            element::Element::Null
+        element::Element::or_print(self.try_load(data))
+    }
+    fn try_load(&self, data: &serde_json::Map<String,serde_json::Value>) -> Result<element::Element,String> {
+        match data.get("settings") {
+            Some(s) => serde_json::from_value::<Block>(s.clone())
+                .map(element::Element::new_gen)
+                .map_err(|e| format!("Failed to load block: {}", e)),
+            None => Ok(element::Element::Null)
         }
-
-        
-        
-        //element::Element::new_gen(serde_json::from_value::<Self>(serde_json::Value::Object(data.clone)).unwrap())
+    }
+    fn position(&self) -> Option<V2<f32>> {
+        Some([self.pos.x + self.shape.x*0.5, self.pos.y + self.shape.y*0.5].into())
     }
     fn sprite(&self) -> Option<sprite::Sprite> {
         Some(self.sprite.clone())
     }
+    fn sprite_mode(&self) -> element::SpriteMode {
+        element::SpriteMode::Static
+    }
 }
\ No newline at end of file
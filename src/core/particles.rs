@@ -69,6 +69,7 @@ pub struct ParticleEmitterSettings<Type:ParticleBehavior> {
     pub spread: f32,
     pub count: u32,
     pub velocity: V2<f32>,
+    #[serde(deserialize_with = "sprite::deserialize_frame")]
     pub sprite_index: usize,
     pub sprite_depth: f32,
 
@@ -203,16 +204,17 @@ impl<Type:ParticleBehavior+ 'static> element::ElementBehavior for ParticleEmitte
         Some(base)
     }
     fn load(&self, data: &serde_json::Map<String,serde_json::Value>) -> element::Element {
-        
+        element::Element::or_print(self.try_load(data))
+    }
+    fn try_load(&self, data: &serde_json::Map<String,serde_json::Value>) -> Result<element::Element,String> {
         let mut s: ParticleEmitter<Type> = Self::default();
 
         if let Some(settings) = data.get("settings") {
-            s.data = serde_json::from_value(settings.clone()).unwrap();
+            s.data = serde_json::from_value(settings.clone()).map_err(|e| format!("Failed to load particle emitter: {}", e))?;
         }
 
-
         s.emit();
-        element::Element::new_module(s)
+        Ok(element::Element::new_module(s))
     }
 }
 
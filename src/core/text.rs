@@ -0,0 +1,103 @@
+use std::rc::Rc;
+
+use crate::*;
+use crate::text::{BitmapFont, TextStyle};
+
+
+#[derive(Clone)]
+pub enum TextEvent {
+    SetText(String),
+    SetColor([f32;4])
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TextSettings {
+    // Module alias other elements send TextEvents to
+    pub id: String,
+    pub text: String,
+    #[serde(flatten)]
+    pub style: TextStyle
+}
+
+
+/// Draws a string with a bitmap font
+/// Register one per font, for example `Element::new_gen(Text::new("title", font))`, scenes then
+/// create it by name with its settings holding the text and style
+#[derive(Clone)]
+pub struct Text {
+    pub name: String,
+    pub font: Rc<BitmapFont>,
+    pub data: TextSettings,
+    sender: event::Sender<TextEvent>,
+    receiver: Rc<event::Receiver<TextEvent>>
+}
+
+impl Text {
+    pub fn new(name: &str, font: BitmapFont) -> Self {
+        let s = event::Sender::new();
+        Self {
+            name: name.to_string(),
+            font: Rc::new(font),
+            data: TextSettings {
+                id: name.to_string(),
+                ..Default::default()
+            },
+            receiver: s.new_receiver(),
+            sender: s
+        }
+    }
+    pub fn with_text(mut self, text: &str) -> Self {
+        self.data.text = text.to_string();
+        self
+    }
+    pub fn with_style(mut self, style: TextStyle) -> Self {
+        self.data.style = style;
+        self
+    }
+}
+
+impl element::ElementBehavior for Text {
+    fn local_update(&mut self, _td: f32) {
+        for e in self.receiver.poll() {
+            match e {
+                TextEvent::SetText(text) => self.data.text = text,
+                TextEvent::SetColor(color) => self.data.style.color = color
+            }
+        }
+    }
+    fn sprite(&self) -> Option<sprite::Sprite> {
+        Some(self.font.layout(&self.data.text, &self.data.style))
+    }
+    fn save(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.name,
+            "settings": self.data
+        })
+    }
+    fn load(&self, data: &serde_json::Map<String,serde_json::Value>) -> element::Element {
+        element::Element::or_print(self.try_load(data))
+    }
+    fn try_load(&self, data: &serde_json::Map<String,serde_json::Value>) -> Result<element::Element,String> {
+        let mut s = Self::new(&self.name, BitmapFont::default());
+        s.font = self.font.clone();
+        s.data = self.data.clone();
+
+        if let Some(settings) = data.get("settings") {
+            s.data = serde_json::from_value(settings.clone()).map_err(|e| format!("Failed to load text: {}", e))?;
+        }
+        if s.data.id.is_empty() {
+            s.data.id = s.name.clone();
+        }
+        Ok(element::Element::new_module(s))
+    }
+}
+
+impl element::ModuleBehavior for Text {
+    fn alias(&self) -> String {
+        self.data.id.clone()
+    }
+    fn component(&self) -> &dyn std::any::Any {
+        &self.sender
+    }
+}
@@ -0,0 +1,529 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use serde::{Deserialize,Serialize};
+
+use crate::*;
+
+
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TileProperties {
+    pub solid: bool,
+    // Only blocks from above, collision handling is left to the colliding element
+    pub one_way: bool,
+    pub hazard: bool,
+    pub tags: Vec<String>
+}
+
+impl TileProperties {
+    pub fn collides(&self) -> bool {
+        self.solid || self.one_way || self.hazard
+    }
+    // Tags given to the collider covering these tiles
+    pub fn collider_tags(&self) -> Vec<String> {
+        let mut tags = self.tags.clone();
+        if self.solid { tags.push("solid".to_string()); }
+        if self.one_way { tags.push("one way".to_string()); }
+        if self.hazard { tags.push("hazard".to_string()); }
+        tags
+    }
+}
+
+
+/// Grid of tiles drawn from the sprite sheet
+/// Rows are listed from the top, `pos` is the bottom left corner of the map
+/// Vertices are baked per chunk, only chunks in view are drawn and changing a tile rebakes its chunk
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tilemap {
+    pub pos: V2<f32>,
+    pub tile_size: V2<f32>,
+    pub width: usize,
+    pub height: usize,
+    #[serde(serialize_with = "serialize_tiles", deserialize_with = "deserialize_tiles")]
+    pub tiles: Vec<Option<usize>>,
+    #[serde(deserialize_with = "deserialize_properties")]
+    pub properties: HashMap<usize, TileProperties>,
+    pub depth: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layer: Option<String>,
+    pub chunk_size: usize,
+    // Terrain tiles are replaced using the JSONManager's terrains when loaded
+    pub autotile: bool,
+
+    // Vertices of each chunk's tiles, baked against the sprite sheet the map is drawn with
+    // None when the chunk needs rebuilding
+    #[serde(skip)]
+    chunks: RefCell<Vec<Option<Vec<Vertex>>>>
+}
+
+impl std::default::Default for Tilemap {
+    fn default() -> Self {
+        Self {
+            pos: [0.0,0.0].into(),
+            tile_size: [1.0,1.0].into(),
+            width: 0,
+            height: 0,
+            tiles: Vec::new(),
+            properties: HashMap::new(),
+            depth: 0.0,
+            layer: None,
+            chunk_size: 16,
+            autotile: false,
+
+            chunks: RefCell::new(Vec::new())
+        }
+    }
+}
+
+impl Tilemap {
+    pub fn new(pos: V2<f32>, width: usize, height: usize) -> Self {
+        Self {
+            pos,
+            width,
+            height,
+            tiles: vec![None; width*height],
+            ..Default::default()
+        }
+    }
+    pub fn with_properties(mut self, tile: usize, properties: TileProperties) -> Self {
+        self.properties.insert(tile, properties);
+        self
+    }
+    pub fn tile(&self, x: usize, y: usize) -> Option<usize> {
+        if x < self.width && y < self.height {
+            self.tiles[y*self.width + x]
+        } else {
+            None
+        }
+    }
+    pub fn set_tile(&mut self, x: usize, y: usize, tile: Option<usize>) {
+        if x < self.width && y < self.height && self.tiles[y*self.width + x] != tile {
+            self.tiles[y*self.width + x] = tile;
+            let chunk = self.chunk_index(x, y);
+            if let Some(c) = self.chunks.get_mut().get_mut(chunk) {
+                *c = None;
+            }
+        }
+    }
+    // Sets a tile and updates the terrain tiles around it
+    pub fn set_tile_autotiled(&mut self, x: usize, y: usize, tile: Option<usize>, terrains: &[core::autotile::Terrain]) {
+        if x >= self.width || y >= self.height {
+            return
+        }
+        let mut tiles = self.tiles.clone();
+        tiles[y*self.width + x] = tile;
+        core::autotile::apply_around(terrains, &mut tiles, self.width, self.height, x, y);
+        for ny in y.saturating_sub(1)..(y+2).min(self.height) {
+            for nx in x.saturating_sub(1)..(x+2).min(self.width) {
+                self.set_tile(nx, ny, tiles[ny*self.width + nx]);
+            }
+        }
+    }
+    pub fn apply_autotile(&mut self, terrains: &[core::autotile::Terrain]) {
+        core::autotile::apply(terrains, &mut self.tiles, self.width, self.height);
+        self.chunks.get_mut().clear();
+    }
+    pub fn properties(&self, tile: usize) -> Option<&TileProperties> {
+        self.properties.get(&tile)
+    }
+    // Tile coordinates containing a world position
+    pub fn tile_at(&self, pos: V2<f32>) -> Option<(usize, usize)> {
+        let x = ((pos.x - self.pos.x) / self.tile_size.x).floor();
+        let y_up = ((pos.y - self.pos.y) / self.tile_size.y).floor();
+        if x < 0.0 || y_up < 0.0 || x >= self.width as f32 || y_up >= self.height as f32 {
+            return None
+        }
+        Some((x as usize, self.height - 1 - y_up as usize))
+    }
+    // World position of the bottom left corner of a tile
+    pub fn tile_pos(&self, x: usize, y: usize) -> V2<f32> {
+        V2 {
+            x: self.pos.x + x as f32 * self.tile_size.x,
+            y: self.pos.y + (self.height - 1 - y) as f32 * self.tile_size.y
+        }
+    }
+
+    fn chunks_wide(&self) -> usize {
+        self.width.div_ceil(self.chunk_size.max(1))
+    }
+    fn chunk_count(&self) -> usize {
+        self.chunks_wide() * self.height.div_ceil(self.chunk_size.max(1))
+    }
+    // Tiles a chunk covers, columns then rows from the top
+    fn chunk_tiles(&self, chunk: usize) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+        let size = self.chunk_size.max(1);
+        let (cx, cy) = (chunk % self.chunks_wide(), chunk / self.chunks_wide());
+        (cx*size..((cx+1)*size).min(self.width), cy*size..((cy+1)*size).min(self.height))
+    }
+    // Bottom left and top right corner of a chunk in world units
+    fn chunk_bounds(&self, chunk: usize) -> (V2<f32>, V2<f32>) {
+        let (columns, rows) = self.chunk_tiles(chunk);
+        (
+            V2 { x: self.pos.x + columns.start as f32 * self.tile_size.x, y: self.pos.y + (self.height - rows.end) as f32 * self.tile_size.y },
+            V2 { x: self.pos.x + columns.end as f32 * self.tile_size.x, y: self.pos.y + (self.height - rows.start) as f32 * self.tile_size.y }
+        )
+    }
+    fn chunk_index(&self, x: usize, y: usize) -> usize {
+        let size = self.chunk_size.max(1);
+        (y / size) * self.chunks_wide() + x / size
+    }
+    fn chunk_sprites(&self, chunk: usize) -> Vec<sprite::Sprite> {
+        let (columns, rows) = self.chunk_tiles(chunk);
+        let mut sprites = Vec::new();
+        for y in rows {
+            for x in columns.clone() {
+                if let Some(tile) = self.tile(x, y) {
+                    let mut t = sprite::Sprite::single(tile)
+                        .with_pos(self.tile_pos(x, y))
+                        .with_scale(self.tile_size)
+                        .with_depth(self.depth);
+                    t.layer = self.layer.clone();
+                    sprites.push(t);
+                }
+            }
+        }
+        sprites
+    }
+    /// Sprites of every tile, chunk by chunk
+    pub fn sprites(&self) -> Vec<sprite::Sprite> {
+        (0..self.chunk_count()).flat_map(|chunk| self.chunk_sprites(chunk)).collect()
+    }
+    // Tile data must cover the map exactly
+    pub(crate) fn check_size(&self) -> Result<(),String> {
+        if self.tiles.len() != self.width*self.height {
+            return Err(format!("Tilemap has {} tiles, expected {} for {}x{}", self.tiles.len(), self.width*self.height, self.width, self.height))
+        }
+        Ok(())
+    }
+
+    /// Merges adjacent colliding tiles with the same properties into rectangles
+    /// Returns the tile rectangle (x, y, width, height) and the properties of each
+    pub fn colliders(&self) -> Vec<((usize, usize, usize, usize), TileProperties)> {
+        let props = |x: usize, y: usize| -> Option<&TileProperties> {
+            self.tile(x, y).and_then(|t| self.properties.get(&t)).filter(|p| p.collides())
+        };
+        let mut used = vec![false; self.width*self.height];
+        let mut colliders = Vec::new();
+        for y in 0..self.height {
+            let mut x = 0;
+            while x < self.width {
+                let Some(p) = props(x, y).filter(|_| !used[y*self.width + x]) else {
+                    x += 1;
+                    continue
+                };
+                // Widest run along the row, then as many rows below as match it exactly
+                let mut w = 1;
+                while x + w < self.width && !used[y*self.width + x + w] && props(x + w, y) == Some(p) {
+                    w += 1;
+                }
+                let mut h = 1;
+                while y + h < self.height && (x..x+w).all(|i| !used[(y+h)*self.width + i] && props(i, y + h) == Some(p)) {
+                    h += 1;
+                }
+                for j in y..y+h {
+                    for i in x..x+w {
+                        used[j*self.width + i] = true;
+                    }
+                }
+                colliders.push(((x, y, w, h), p.clone()));
+                x += w;
+            }
+        }
+        colliders
+    }
+}
+
+
+impl element::ElementBehavior for Tilemap {
+    fn init(&mut self, _uuid: Uuid, mods: &element::ModuleTool) {
+        let mut colliders = Vec::new();
+        for ((x, y, w, h), p) in self.colliders() {
+            let bottom_left = self.tile_pos(x, y + h - 1);
+            colliders.push((Uuid::new_v4(), bottom_left, V2 { x: w as f32 * self.tile_size.x, y: h as f32 * self.tile_size.y }, p.collider_tags()));
+        }
+        mods.access("pom", |pom: &core::physics::POMComponent| {
+            let sender = pom.new_sender();
+            for (uuid, pos, shape, _) in &colliders {
+                sender.send(core::physics::PhysEvent::Static(
+                    Some(*uuid),
+                    core::physics::PhysObj {
+                        pos: *pos,
+                        shape: *shape,
+                        ..Default::default()
+                    }
+                ));
+            }
+        });
+        mods.access("uuid tags", |ut: &scene::UuidTags| {
+            for (uuid, _, _, tags) in &colliders {
+                ut.set_tags(uuid, tags.clone());
+            }
+        });
+    }
+    fn load(&self, data: &serde_json::Map<String,serde_json::Value>) -> element::Element {
+        element::Element::or_print(self.try_load(data))
+    }
+    fn try_load(&self, data: &serde_json::Map<String,serde_json::Value>) -> Result<element::Element,String> {
+        let Some(s) = data.get("settings") else {
+            return Ok(element::Element::Null)
+        };
+        let tilemap = serde_json::from_value::<Tilemap>(s.clone()).map_err(|e| e.to_string())
+            .and_then(|t| t.check_size().map(|_| t))
+            .map_err(|e| format!("Failed to load tilemap: {}", e))?;
+        Ok(element::Element::new_gen(tilemap))
+    }
+    fn save(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": "tilemap",
+            "settings": self
+        })
+    }
+    // One item per chunk in view, chunks are only baked again after one of their tiles changed
+    fn draw_items(&self, sprite_sheet: &sprite::SpriteSheet, layers: &[render::RenderLayer], order: usize, visible: &render::VisibleArea) -> Option<Vec<render::DrawItem>> {
+        let layer = render::layer_index(layers, self.layer.as_deref());
+        let mut chunks = self.chunks.borrow_mut();
+        chunks.resize(self.chunk_count(), None);
+
+        let mut items = Vec::new();
+        for (idx, chunk) in chunks.iter_mut().enumerate() {
+            let (min, max) = self.chunk_bounds(idx);
+            if !visible.shows(layer, min, max) {
+                continue;
+            }
+            let vertices = chunk.get_or_insert_with(|| {
+                self.chunk_sprites(idx).iter().flat_map(|s| sprite_sheet.sprite_vertices(s)).collect()
+            });
+            if !vertices.is_empty() {
+                items.push(render::DrawItem {
+                    layer,
+                    depth: self.depth,
+                    y: min.y,
+                    order,
+                    translucent: false,
+                    vertices: vertices.clone()
+                });
+            }
+        }
+        Some(items)
+    }
+}
+
+
+
+// Tiles are stored as comma separated run-length encoded tokens, `count*tile`
+// where tile is an index, a frame name, or `.` for an empty tile
+fn serialize_tiles<S: serde::Serializer>(tiles: &[Option<usize>], serializer: S) -> Result<S::Ok, S::Error> {
+    let mut tokens = Vec::new();
+    let mut iter = tiles.iter().peekable();
+    while let Some(tile) = iter.next() {
+        let mut count = 1;
+        while iter.peek() == Some(&tile) {
+            iter.next();
+            count += 1;
+        }
+        let t = tile.map_or(".".to_string(), |t| t.to_string());
+        tokens.push(if count > 1 { format!("{}*{}", count, t) } else { t });
+    }
+    serializer.serialize_str(&tokens.join(","))
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TileData {
+    Encoded(String),
+    // Rows of tiles, null for empty
+    Rows(Vec<Vec<Option<sprite::Frame>>>)
+}
+
+pub fn decode_tiles(data: &str) -> Result<Vec<Option<usize>>, String> {
+    let mut tiles = Vec::new();
+    for token in data.split(',').map(|t| t.trim()).filter(|t| !t.is_empty()) {
+        let (count, tile) = match token.split_once('*') {
+            Some((count, tile)) => (count.trim().parse::<usize>().map_err(|_| format!("Invalid tile count in \"{}\"", token))?, tile.trim()),
+            None => (1, token)
+        };
+        let tile = match tile {
+            "." => None,
+            t => Some(t.parse::<usize>().or_else(|_| sprite::frame_index(t))?)
+        };
+        tiles.extend(std::iter::repeat_n(tile, count));
+    }
+    Ok(tiles)
+}
+
+// Applies terrains to tilemap settings that have autotiling enabled
+pub fn autotile_settings(settings: &serde_json::Map<String,serde_json::Value>, terrains: &[core::autotile::Terrain]) -> Result<serde_json::Map<String,serde_json::Value>,String> {
+    let mut tilemap = serde_json::from_value::<Tilemap>(serde_json::Value::Object(settings.clone())).map_err(|e| e.to_string())?;
+    tilemap.check_size()?;
+    tilemap.apply_autotile(terrains);
+    match serde_json::to_value(&tilemap) {
+        Ok(serde_json::Value::Object(map)) => Ok(map),
+        _ => Err("Could not save tilemap".to_string())
+    }
+}
+
+fn deserialize_tiles<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<Option<usize>>, D::Error> {
+    match TileData::deserialize(deserializer)? {
+        TileData::Encoded(s) => decode_tiles(&s).map_err(serde::de::Error::custom),
+        TileData::Rows(rows) => {
+            let mut tiles = Vec::new();
+            for row in rows {
+                for t in row {
+                    tiles.push(match t {
+                        Some(f) => Some(f.resolve().map_err(serde::de::Error::custom)?),
+                        None => None
+                    });
+                }
+            }
+            Ok(tiles)
+        }
+    }
+}
+
+// Property keys may be tile indices or frame names
+fn deserialize_properties<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<HashMap<usize, TileProperties>, D::Error> {
+    let named: HashMap<String, TileProperties> = HashMap::deserialize(deserializer)?;
+    let mut properties = HashMap::new();
+    for (key, p) in named {
+        let tile = key.parse::<usize>().or_else(|_| sprite::frame_index(&key)).map_err(serde::de::Error::custom)?;
+        properties.insert(tile, p);
+    }
+    Ok(properties)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use element::ElementBehavior;
+
+    // Tile and position of each sprite
+    fn tiles(tilemap: &Tilemap) -> Vec<(usize, [f32;2])> {
+        tilemap.sprites().iter().map(|t| (t.tex_indices[0][0], [t.pos.x, t.pos.y])).collect()
+    }
+
+    fn draw(tilemap: &Tilemap, visible: &render::VisibleArea) -> Vec<render::DrawItem> {
+        tilemap.draw_items(&sprite::SpriteSheet::new((8,8)), &render::default_layers(), 0, visible).unwrap()
+    }
+
+    // Where the baked vertices of each chunk are, to tell which chunks were baked again
+    fn baked(tilemap: &Tilemap) -> Vec<Option<*const Vertex>> {
+        tilemap.chunks.borrow().iter().map(|c| c.as_ref().map(|v| v.as_ptr())).collect()
+    }
+
+    #[test]
+    fn decodes_run_length_tiles() {
+        assert_eq!(decode_tiles("3*1, .,2*., 4").unwrap(), vec![Some(1), Some(1), Some(1), None, None, None, Some(4)]);
+        assert!(decode_tiles("x*1").is_err());
+        assert!(decode_tiles("grass").is_err());
+
+        let frames = std::rc::Rc::new(HashMap::from([("grass".to_string(), 7)]));
+        assert_eq!(sprite::with_frames(&frames, || decode_tiles("2*grass,1")).unwrap(), vec![Some(7), Some(7), Some(1)]);
+    }
+
+    #[test]
+    fn encodes_tiles_it_decodes() {
+        let mut tilemap = Tilemap::new([0.0,0.0].into(), 4, 2);
+        tilemap.tiles = vec![Some(2), Some(2), None, Some(5), None, None, None, Some(2)];
+        let data = serde_json::to_value(&tilemap).unwrap();
+        assert_eq!(data["tiles"], "2*2,.,5,3*.,2");
+
+        let loaded = serde_json::from_value::<Tilemap>(data).unwrap();
+        assert_eq!(loaded.tiles, tilemap.tiles);
+    }
+
+    #[test]
+    fn rejects_tiles_not_covering_the_map() {
+        let tilemap = serde_json::from_value::<Tilemap>(serde_json::json!({"width": 2, "height": 2, "tiles": "3*1"})).unwrap();
+        assert!(tilemap.check_size().is_err());
+        assert!(autotile_settings(serde_json::json!({"width": 2, "height": 2, "tiles": "5*1"}).as_object().unwrap(), &[]).is_err());
+        assert!(matches!(Tilemap::default().load(serde_json::json!({"settings": {"width": 2, "height": 1, "tiles": "1"}}).as_object().unwrap()), element::Element::Null));
+    }
+
+    #[test]
+    fn chunks_keep_tile_order() {
+        let mut tilemap = Tilemap::new([0.0,0.0].into(), 3, 3);
+        tilemap.chunk_size = 2;
+        tilemap.tiles = (0..9).map(Some).collect();
+        tilemap.tiles[4] = None;
+
+        let order: Vec<usize> = tiles(&tilemap).into_iter().map(|(t, _)| t).collect();
+        // Chunks left to right then downwards, rows from the top within each
+        assert_eq!(order, vec![0, 1, 3, 2, 5, 6, 7, 8]);
+
+        tilemap.set_tile(2, 2, Some(20));
+        assert_eq!(tiles(&tilemap).last().unwrap(), &(20, [2.0, 0.0]));
+    }
+
+    #[test]
+    fn rebakes_only_the_edited_chunk() {
+        let mut tilemap = Tilemap::new([0.0,0.0].into(), 4, 4);
+        tilemap.chunk_size = 2;
+        tilemap.tiles = vec![Some(1); 16];
+
+        let items = draw(&tilemap, &render::VisibleArea::all());
+        assert_eq!(items.len(), 4);
+        assert!(items.iter().all(|i| i.vertices.len() == 4*6));
+        let before = baked(&tilemap);
+
+        // Bottom right chunk
+        tilemap.set_tile(3, 3, Some(2));
+        assert_eq!(baked(&tilemap)[..3], before[..3]);
+        assert_eq!(baked(&tilemap)[3], None);
+
+        draw(&tilemap, &render::VisibleArea::all());
+        let after = baked(&tilemap);
+        assert_eq!(after[..3], before[..3]);
+        assert!(after[3].is_some());
+    }
+
+    #[test]
+    fn skips_chunks_out_of_view() {
+        let mut tilemap = Tilemap::new([0.0,0.0].into(), 4, 4);
+        tilemap.chunk_size = 2;
+        tilemap.tiles = vec![Some(1); 16];
+
+        // Only the top left chunk, which covers (0, 2) to (2, 4), is in view on the world layer
+        let mut visible = render::VisibleArea::new(4);
+        visible.add(1, [0.5, 2.5].into(), [1.5, 3.5].into());
+        let items = draw(&tilemap, &visible);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].y, 2.0);
+        assert_eq!(baked(&tilemap).iter().filter(|c| c.is_some()).count(), 1);
+
+        assert!(draw(&tilemap, &render::VisibleArea::new(4)).is_empty());
+    }
+
+    #[test]
+    fn draws_large_maps() {
+        let mut tilemap = Tilemap::new([0.0,0.0].into(), 300, 300);
+        tilemap.tiles = vec![Some(1); 300*300];
+        let vertices: usize = draw(&tilemap, &render::VisibleArea::all()).iter().map(|i| i.vertices.len()).sum();
+        assert_eq!(vertices, 300*300*6);
+    }
+
+    #[test]
+    fn merges_colliders() {
+        let solid = TileProperties { solid: true, ..Default::default() };
+        let hazard = TileProperties { hazard: true, ..Default::default() };
+        let mut tilemap = Tilemap::new([0.0,0.0].into(), 4, 3)
+            .with_properties(1, solid.clone())
+            .with_properties(2, hazard.clone())
+            .with_properties(3, TileProperties::default());
+        // 1 1 2 .
+        // 1 1 2 3
+        // 1 . . 1
+        tilemap.tiles = vec![
+            Some(1), Some(1), Some(2), None,
+            Some(1), Some(1), Some(2), Some(3),
+            Some(1), None, None, Some(1)
+        ];
+        assert!(tilemap.colliders() == vec![
+            ((0, 0, 2, 2), solid.clone()),
+            ((2, 0, 1, 2), hazard),
+            ((0, 2, 1, 1), solid.clone()),
+            ((3, 2, 1, 1), solid)
+        ]);
+    }
+}
@@ -0,0 +1,517 @@
+use std::rc::Rc;
+
+use crate::*;
+use crate::text::{Align, BitmapFont, TextStyle};
+
+
+/// Point of the window a widget is positioned from, also used as the widget's pivot
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight
+}
+
+impl Anchor {
+    // Fraction of the width and height from the bottom left
+    pub fn fraction(&self) -> V2<f32> {
+        match self {
+            Anchor::TopLeft => [0.0,1.0],
+            Anchor::Top => [0.5,1.0],
+            Anchor::TopRight => [1.0,1.0],
+            Anchor::Left => [0.0,0.5],
+            Anchor::Center => [0.5,0.5],
+            Anchor::Right => [1.0,0.5],
+            Anchor::BottomLeft => [0.0,0.0],
+            Anchor::Bottom => [0.5,0.0],
+            Anchor::BottomRight => [1.0,0.0]
+        }.into()
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WidgetKind {
+    Label,
+    Panel,
+    Button,
+    ProgressBar,
+    Frame,
+    // Takes the text input focus while focused
+    TextField
+}
+
+
+/// Sizes and offsets are in window pixels
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Widget {
+    pub id: String,
+    pub kind: WidgetKind,
+    pub anchor: Anchor,
+    // From the anchor point, positive values go right and up
+    pub offset: V2<f32>,
+    pub size: V2<f32>,
+    pub visible: bool,
+
+    pub text: String,
+    pub text_size: f32,
+    pub text_color: [f32;4],
+    pub align: Align,
+
+    // Background, stretched over the widget
+    #[serde(deserialize_with = "sprite::deserialize_frame")]
+    pub tile: usize,
+    // Left, right, bottom and top borders of the tile as fractions of it, frames always nine-slice
+    // the tile and buttons and bars do when this is set
+    pub insets: Option<[f32;4]>,
+    // Size in pixels the whole tile is drawn at, which sets the size of nine-sliced borders
+    pub tile_scale: f32,
+    pub color: [f32;4],
+    pub focus_color: [f32;4],
+
+    // Progress bars fill from the left
+    pub value: f32,
+    pub fill_color: [f32;4]
+}
+
+impl std::default::Default for Widget {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            kind: WidgetKind::Panel,
+            anchor: Anchor::TopLeft,
+            offset: [0.0,0.0].into(),
+            size: [100.0,20.0].into(),
+            visible: true,
+
+            text: String::new(),
+            text_size: 16.0,
+            text_color: [1.0,1.0,1.0,1.0],
+            align: Align::Left,
+
+            tile: 0,
+            insets: None,
+            tile_scale: 16.0,
+            color: [1.0,1.0,1.0,1.0],
+            focus_color: [1.0,1.0,0.6,1.0],
+
+            value: 0.0,
+            fill_color: [0.2,0.8,0.2,1.0]
+        }
+    }
+}
+
+impl Widget {
+    pub fn new(id: &str, kind: WidgetKind) -> Self {
+        Self {
+            id: id.to_string(),
+            kind,
+            ..Default::default()
+        }
+    }
+    pub fn with_anchor(mut self, anchor: Anchor, offset: V2<f32>) -> Self {
+        self.anchor = anchor;
+        self.offset = offset;
+        self
+    }
+    pub fn with_size(mut self, size: V2<f32>) -> Self {
+        self.size = size;
+        self
+    }
+    pub fn with_text(mut self, text: &str) -> Self {
+        self.text = text.to_string();
+        self
+    }
+    pub fn with_tile(mut self, tile: usize) -> Self {
+        self.tile = tile;
+        self
+    }
+    pub fn with_color(mut self, color: [f32;4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn focusable(&self) -> bool {
+        self.visible && matches!(self.kind, WidgetKind::Button | WidgetKind::TextField)
+    }
+
+    // Bottom left and size in window pixels
+    pub fn rect(&self, window_size: [u32;2]) -> (V2<f32>, V2<f32>) {
+        let f = self.anchor.fraction();
+        let anchor = [window_size[0] as f32 * f.x + self.offset.x, window_size[1] as f32 * f.y + self.offset.y];
+        ([anchor[0] - self.size.x * f.x, anchor[1] - self.size.y * f.y].into(), self.size)
+    }
+    pub fn contains(&self, window_size: [u32;2], point: V2<f32>) -> bool {
+        let (pos, size) = self.rect(window_size);
+        point.x >= pos.x && point.y >= pos.y && point.x < pos.x + size.x && point.y < pos.y + size.y
+    }
+}
+
+
+#[derive(Clone)]
+pub enum UiEvent {
+    // Pointer position in window pixels from the top left, as reported by the window
+    // Widgets are laid out in render target pixels, see scene::Screen::window_to_target
+    Pointer { pos: V2<f32>, down: bool },
+    SetText(String, String),
+    SetValue(String, f32),
+    SetVisible(String, bool),
+    Focus(Option<String>)
+}
+
+#[derive(Clone, Debug)]
+pub enum UiAction {
+    Pressed(String),
+    FocusChanged(Option<String>),
+    // Enter was pressed in a text field, with its text
+    Submitted(String, String)
+}
+
+/// Component of the ui module, send UiEvents with `sender` and listen to `actions`
+#[derive(Clone)]
+pub struct UiHandle {
+    pub sender: event::Sender<UiEvent>,
+    pub actions: event::Sender<UiAction, event::Locked>
+}
+
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiSettings {
+    pub id: String,
+    pub layer: String,
+    pub depth: f32,
+    pub widgets: Vec<Widget>
+}
+
+impl std::default::Default for UiSettings {
+    fn default() -> Self {
+        Self {
+            id: "ui".to_string(),
+            layer: "ui".to_string(),
+            depth: 0.0,
+            widgets: Vec::new()
+        }
+    }
+}
+
+
+/// Widgets drawn on a screen space layer and anchored to the window edges
+/// Tab moves the keyboard focus between buttons and text fields, see `Ui::default_bindings` for the other keys
+/// Navigation keys other than tab are left alone while no widget is focused
+#[derive(Clone)]
+pub struct Ui {
+    pub name: String,
+    pub font: Rc<BitmapFont>,
+    pub data: UiSettings,
+    focus: Option<usize>,
+    // Button the pointer went down on
+    pressed: Option<usize>,
+    // Last cursor position in window pixels
+    cursor: Option<V2<f32>>,
+    screen: scene::Screen,
+
+    handle: UiHandle,
+    actions: event::Sender<UiAction>,
+    receiver: Rc<event::Receiver<UiEvent>>,
+    input_receiver: Rc<event::Receiver<instance::InputEvent>>,
+    input: Option<instance::InputManager>,
+    bindings: input::ActionMap,
+    text_receiver: Rc<event::Receiver<input::TextInputEvent>>,
+    uuid: Uuid
+}
+
+impl Ui {
+    pub fn new(name: &str, font: BitmapFont) -> Self {
+        let sender = event::Sender::new();
+        let actions = event::Sender::new();
+        Self {
+            name: name.to_string(),
+            font: Rc::new(font),
+            data: UiSettings::default(),
+            focus: None,
+            pressed: None,
+            cursor: None,
+            screen: scene::Screen::default(),
+
+            handle: UiHandle {
+                sender: sender.clone(),
+                actions: actions.lock()
+            },
+            actions,
+            receiver: sender.new_receiver(),
+            input_receiver: event::Receiver::new(),
+            input: None,
+            bindings: input::ActionMap::new(Self::default_bindings()),
+            text_receiver: event::Receiver::new(),
+            uuid: Uuid::nil()
+        }
+    }
+    pub fn with_widget(mut self, widget: Widget) -> Self {
+        self.data.widgets.push(widget);
+        self
+    }
+    pub fn with_bindings(mut self, bindings: input::Bindings) -> Self {
+        self.bindings = input::ActionMap::new(bindings);
+        self
+    }
+    // "cycle" moves the focus forward from anywhere, also out of text fields
+    // "next", "previous" and "press" only apply while a widget other than a text field is focused
+    pub fn default_bindings() -> input::Bindings {
+        use input::{Binding, GamepadButton as G, VirtualKeyCode as K};
+        let key = |k: K| Binding::key(k);
+        input::Bindings::new()
+            .with_action("cycle", vec![key(K::Tab)])
+            .with_action("next", vec![key(K::Down), key(K::Right), Binding::GamepadButton(G::DPadDown), Binding::GamepadButton(G::DPadRight)])
+            .with_action("previous", vec![key(K::Up), key(K::Left), Binding::GamepadButton(G::DPadUp), Binding::GamepadButton(G::DPadLeft)])
+            .with_action("press", vec![key(K::Return), key(K::Space), Binding::GamepadButton(G::South)])
+    }
+
+    pub fn widget(&self, id: &str) -> Option<&Widget> {
+        self.data.widgets.iter().find(|w| w.id == id)
+    }
+    pub fn widget_mut(&mut self, id: &str) -> Option<&mut Widget> {
+        self.data.widgets.iter_mut().find(|w| w.id == id)
+    }
+    pub fn focused(&self) -> Option<&Widget> {
+        self.focus.and_then(|idx| self.data.widgets.get(idx))
+    }
+
+    fn set_focus(&mut self, focus: Option<usize>) {
+        if focus != self.focus {
+            self.focus = focus;
+            self.actions.send(UiAction::FocusChanged(self.focused().map(|w| w.id.clone())));
+            if let Some(input) = &self.input {
+                if self.editing() {
+                    input.begin_text_input(self.uuid);
+                } else {
+                    input.end_text_input(self.uuid);
+                }
+            }
+        }
+    }
+    // Whether the focused widget is a text field
+    fn editing(&self) -> bool {
+        self.focused().is_some_and(|w| w.kind == WidgetKind::TextField)
+    }
+    // Next focusable widget in either direction, wrapping around
+    fn move_focus(&mut self, forward: bool) {
+        let count = self.data.widgets.len();
+        if count == 0 {
+            return;
+        }
+        let start = self.focus.unwrap_or(if forward { count - 1 } else { 0 });
+        for step in 1..=count {
+            let idx = if forward { (start + step) % count } else { (start + count - step) % count };
+            if self.data.widgets[idx].focusable() {
+                self.set_focus(Some(idx));
+                return;
+            }
+        }
+    }
+    fn press(&mut self, idx: usize) {
+        let id = self.data.widgets[idx].id.clone();
+        self.actions.send(UiAction::Pressed(id));
+    }
+
+    fn pointer(&mut self, pos: V2<f32>, down: bool) {
+        let window_size = self.screen.size();
+        let point: V2<f32> = match self.screen.window_to_target(pos) {
+            Some(p) => p.into(),
+            None => [-1.0,-1.0].into()
+        };
+        // Later widgets are drawn on top, so they are hit first
+        let hit = self.data.widgets.iter().rposition(|w| w.focusable() && w.contains(window_size, point));
+        if hit.is_some() {
+            self.set_focus(hit);
+        }
+        match (down, self.pressed) {
+            (true, None) => self.pressed = hit,
+            (false, Some(pressed)) => {
+                self.pressed = None;
+                if hit == Some(pressed) {
+                    self.press(pressed);
+                }
+            },
+            _ => ()
+        }
+    }
+
+    fn background(&self, widget: &Widget, pos: V2<f32>, size: V2<f32>, color: [f32;4], depth: f32, base: &mut sprite::Sprite) {
+        let mut sprite = sprite::Sprite::single(widget.tile)
+            .with_pos(pos).with_depth(depth).with_color(color)
+            .with_layer(&self.data.layer).translucent(color[3] < 1.0);
+        let insets = match widget.kind {
+            WidgetKind::Frame => Some(widget.insets.unwrap_or([0.25,0.25,0.25,0.25])),
+            _ => widget.insets
+        };
+        sprite = match insets {
+            Some(insets) => sprite.with_scale([widget.tile_scale, widget.tile_scale].into()).with_nine_slice(insets, size),
+            None => sprite.with_scale(size)
+        };
+        base.next(sprite);
+    }
+
+    fn label(&self, widget: &Widget, pos: V2<f32>, size: V2<f32>, depth: f32, centered: bool, base: &mut sprite::Sprite) {
+        if widget.text.is_empty() {
+            return;
+        }
+        let mut style = TextStyle::new([pos.x, pos.y + size.y].into(), widget.text_size)
+            .with_align(widget.align)
+            .with_color(widget.text_color)
+            .with_depth(depth)
+            .with_layer(&self.data.layer);
+        if size.x > 0.0 {
+            style = style.with_wrap(size.x);
+        }
+        if centered {
+            let height = self.font.bounds(&widget.text, &style).y;
+            style.pos.y = pos.y + (size.y + height) * 0.5;
+        }
+        if let Some(glyphs) = self.font.layout(&widget.text, &style).next {
+            base.next(*glyphs);
+        }
+    }
+}
+
+impl element::ElementBehavior for Ui {
+    fn init(&mut self, uuid: Uuid, mods: &element::ModuleTool) {
+        self.uuid = uuid;
+        mods.access("screen", |screen: &scene::Screen| {
+            self.screen = screen.clone();
+        });
+        if let Some(input) = mods.input() {
+            self.input_receiver = input.new_receiver();
+            self.text_receiver = input.text_receiver(uuid);
+            self.cursor = input.cursor().window;
+            self.input = Some(input);
+        }
+    }
+    fn local_update(&mut self, _td: f32) {
+        for e in self.receiver.poll() {
+            match e {
+                UiEvent::Pointer { pos, down } => self.pointer(pos, down),
+                UiEvent::SetText(id, text) => if let Some(w) = self.widget_mut(&id) { w.text = text; },
+                UiEvent::SetValue(id, value) => if let Some(w) = self.widget_mut(&id) { w.value = value.clamp(0.0, 1.0); },
+                UiEvent::SetVisible(id, visible) => if let Some(w) = self.widget_mut(&id) { w.visible = visible; },
+                UiEvent::Focus(id) => {
+                    let idx = id.and_then(|id| self.data.widgets.iter().position(|w| w.id == id && w.focusable()));
+                    self.set_focus(idx);
+                }
+            }
+        }
+
+        for e in self.text_receiver.poll() {
+            let Some(idx) = self.focus.filter(|_| self.editing()) else {
+                continue;
+            };
+            let widget = &mut self.data.widgets[idx];
+            match e {
+                input::TextInputEvent::Char(c) => widget.text.push(c),
+                input::TextInputEvent::Backspace => { widget.text.pop(); },
+                input::TextInputEvent::Submit => self.actions.send(UiAction::Submitted(widget.id.clone(), widget.text.clone())),
+                _ => ()
+            }
+        }
+
+        let gamepads = self.input.as_ref().map(|i| i.gamepads()).unwrap_or_default();
+        for e in self.input_receiver.poll() {
+            for action in self.bindings.update(&e, &gamepads) {
+                let input::ActionEvent::Pressed { action, .. } = action else {
+                    continue;
+                };
+                // Only cycling leaves a text field, the other keys are typed into it
+                let navigating = self.focus.is_some() && !self.editing();
+                match action.as_str() {
+                    "cycle" => self.move_focus(true),
+                    "next" if navigating => self.move_focus(true),
+                    "previous" if navigating => self.move_focus(false),
+                    "press" if navigating => if let Some(idx) = self.focus { self.press(idx); },
+                    _ => ()
+                }
+            }
+            match e {
+                instance::InputEvent::CursorMoved { window, .. } => self.cursor = Some(window),
+                instance::InputEvent::MouseDown(instance::MouseButton::Left) => if let Some(pos) = self.cursor { self.pointer(pos, true); },
+                instance::InputEvent::MouseUp(instance::MouseButton::Left) => if let Some(pos) = self.cursor { self.pointer(pos, false); },
+                _ => ()
+            }
+        }
+
+        // Focus can't stay on a widget that was hidden
+        if self.focused().is_some_and(|w| !w.focusable()) {
+            self.set_focus(None);
+        }
+    }
+    fn sprite(&self) -> Option<sprite::Sprite> {
+        let mut base = sprite::Sprite::empty();
+        let window_size = self.screen.size();
+
+        for (idx, widget) in self.data.widgets.iter().enumerate() {
+            if !widget.visible {
+                continue;
+            }
+            // Later widgets in front, text in front of its widget
+            let depth = self.data.depth - idx as f32 * 0.001;
+            let (pos, size) = widget.rect(window_size);
+            let color = if self.focus == Some(idx) { widget.focus_color } else { widget.color };
+
+            match widget.kind {
+                WidgetKind::Label => self.label(widget, pos, size, depth, false, &mut base),
+                WidgetKind::Panel | WidgetKind::Frame => self.background(widget, pos, size, color, depth, &mut base),
+                WidgetKind::Button | WidgetKind::TextField => {
+                    self.background(widget, pos, size, color, depth, &mut base);
+                    self.label(widget, pos, size, depth - 0.0005, true, &mut base);
+                },
+                WidgetKind::ProgressBar => {
+                    self.background(widget, pos, size, color, depth, &mut base);
+                    let fill = [size.x * widget.value.clamp(0.0, 1.0), size.y].into();
+                    if widget.value > 0.0 {
+                        base.next(sprite::Sprite::single(widget.tile)
+                            .with_pos(pos).with_scale(fill).with_depth(depth - 0.0005)
+                            .with_color(widget.fill_color).with_layer(&self.data.layer)
+                            .translucent(widget.fill_color[3] < 1.0));
+                    }
+                }
+            }
+        }
+        Some(base)
+    }
+    fn save(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.name,
+            "settings": self.data
+        })
+    }
+    fn load(&self, data: &serde_json::Map<String,serde_json::Value>) -> element::Element {
+        element::Element::or_print(self.try_load(data))
+    }
+    fn try_load(&self, data: &serde_json::Map<String,serde_json::Value>) -> Result<element::Element,String> {
+        let mut s = Self::new(&self.name, BitmapFont::default());
+        s.font = self.font.clone();
+        s.data = self.data.clone();
+
+        if let Some(settings) = data.get("settings") {
+            s.data = serde_json::from_value(settings.clone()).map_err(|e| format!("Failed to load ui: {}", e))?;
+        }
+        Ok(element::Element::new_module(s))
+    }
+}
+
+impl element::ModuleBehavior for Ui {
+    fn alias(&self) -> String {
+        self.data.id.clone()
+    }
+    fn component(&self) -> &dyn std::any::Any {
+        &self.handle
+    }
+}
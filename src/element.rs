@@ -9,6 +9,8 @@ pub trait ElementBehavior {
 	
 	fn save(&self) -> serde_json::Value { serde_json::Value::Null }
 	fn load(&self, data: &serde_json::Map<String,serde_json::Value>) -> Element { Element::Null }
+    // Like load, but with the reason the data couldn't be loaded, used by the JSONManager
+    fn try_load(&self, data: &serde_json::Map<String,serde_json::Value>) -> Result<Element,String> { Ok(self.load(data)) }
 
     fn clip_matrix(&self, window_size: [u32;2]) -> [[f32;3];3] {
 		[[1.0, 0.0, 0.0],
@@ -16,9 +18,28 @@ pub trait ElementBehavior {
 		[0.0, 0.0, 1.0]]
 	}
 	fn offset(&self) -> [f32;2] { [0.0,0.0] }
+    // Where and what a camera draws, only used for elements registered as cameras
+    fn viewport(&self) -> render::Viewport { render::Viewport::default() }
+    // World position other elements can track, such as a following camera
+    fn position(&self) -> Option<V2<f32>> { None }
 
 
     fn sprite(&self) -> Option<sprite::Sprite> { None }
+    fn sprite_mode(&self) -> SpriteMode { SpriteMode::Dynamic }
+    // Static elements return true once after their sprite has changed
+    fn dirty(&mut self) -> bool { false }
+    // Elements that bake their own vertices, such as tilemaps, return them here instead of a sprite
+    // Anything outside `visible` can be left out
+    fn draw_items(&self, _sprite_sheet: &sprite::SpriteSheet, _layers: &[render::RenderLayer], _order: usize, _visible: &render::VisibleArea) -> Option<Vec<render::DrawItem>> { None }
+}
+
+
+/// Static sprites are baked into a cached buffer that is only rebuilt when they change,
+/// dynamic sprites are rebuilt every frame
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum SpriteMode {
+    Static,
+    Dynamic
 }
 
 
@@ -74,6 +95,21 @@ impl Element {
             Element::Null => Element::Null
         }
     }
+    pub fn try_load(&self, data: &serde_json::Map<String,serde_json::Value>) -> Result<Element,String> {
+        match self {
+            Element::Gen(e) => e.borrow().try_load(data),
+            Element::Module(m) => m.borrow().try_load(data),
+            Element::Group(g) => g.borrow().iter().map(|e| e.try_load(data)).collect::<Result<Vec<_>,_>>().map(|v| Self::Group(v.into())),
+            Element::Null => Ok(Element::Null)
+        }
+    }
+    // For load implementations that go through try_load
+    pub fn or_print(result: Result<Element,String>) -> Element {
+        result.unwrap_or_else(|e| {
+            println!("{}", e);
+            Element::Null
+        })
+    }
     pub fn save(&self) -> serde_json::Value {
         match self {
             Element::Gen(e) => e.borrow_mut().save(),
@@ -110,6 +146,23 @@ impl Element {
 
 
 
+    pub fn viewport(&self) -> render::Viewport {
+		match self {
+            element::Element::Gen(e) => e.borrow().viewport(),
+            element::Element::Module(m) => m.borrow().viewport(),
+            element::Element::Group(_) => render::Viewport::default(),
+			element::Element::Null => render::Viewport::default()
+		}
+	}
+    pub fn position(&self) -> Option<V2<f32>> {
+		match self {
+            element::Element::Gen(e) => e.borrow().position(),
+            element::Element::Module(m) => m.borrow().position(),
+            element::Element::Group(_) => None,
+			element::Element::Null => None
+		}
+	}
+
     pub fn sprite(&self) -> Option<sprite::Sprite> {
 		match self {
             element::Element::Gen(e) => e.borrow_mut().sprite(),
@@ -126,6 +179,51 @@ impl Element {
 			element::Element::Null => None
 		}
 	}
+    // Items from draw_items, or split from the sprite of elements that don't bake their own
+    pub fn draw_items(&self, sprite_sheet: &sprite::SpriteSheet, layers: &[render::RenderLayer], order: usize, visible: &render::VisibleArea) -> Vec<render::DrawItem> {
+        let from_sprite = |sprite: Option<sprite::Sprite>| {
+            let mut items = Vec::new();
+            if let Some(s) = sprite {
+                render::sprite_items(s, sprite_sheet, layers, order, &mut items);
+            }
+            items
+        };
+        match self {
+            Element::Gen(e) => {
+                let e = e.borrow();
+                e.draw_items(sprite_sheet, layers, order, visible).unwrap_or_else(|| from_sprite(e.sprite()))
+            },
+            Element::Module(m) => {
+                let m = m.borrow();
+                m.draw_items(sprite_sheet, layers, order, visible).unwrap_or_else(|| from_sprite(m.sprite()))
+            },
+            Element::Group(g) => g.borrow().iter().flat_map(|e| e.draw_items(sprite_sheet, layers, order, visible)).collect(),
+            Element::Null => Vec::new()
+        }
+    }
+    pub fn sprite_mode(&self) -> SpriteMode {
+        match self {
+            Element::Gen(e) => e.borrow().sprite_mode(),
+            Element::Module(m) => m.borrow().sprite_mode(),
+            Element::Group(g) => {
+                let g = g.borrow();
+                if !g.is_empty() && g.iter().all(|e| e.sprite_mode() == SpriteMode::Static) {
+                    SpriteMode::Static
+                } else {
+                    SpriteMode::Dynamic
+                }
+            },
+            Element::Null => SpriteMode::Dynamic
+        }
+    }
+    pub fn dirty(&self) -> bool {
+        match self {
+            Element::Gen(e) => e.borrow_mut().dirty(),
+            Element::Module(m) => m.borrow_mut().dirty(),
+            Element::Group(g) => g.borrow().iter().any(|e| e.dirty()),
+            Element::Null => false
+        }
+    }
 }
 
 
@@ -147,6 +245,26 @@ impl<'a> ModuleTool<'a> {
             scene: scene
         }
     }
+    /// Window pixels from the top left to world units, through the scene's cameras
+    pub fn screen_to_world(&self, pos: V2<f32>) -> Option<V2<f32>> {
+        self.scene.screen_to_world(pos)
+    }
+    /// World units to window pixels from the top left, through the scene's first camera
+    pub fn world_to_screen(&self, pos: V2<f32>) -> Option<V2<f32>> {
+        self.scene.world_to_screen(pos)
+    }
+    /// Input shared by every scene, for polling held and just pressed input
+    pub fn input(&self) -> Option<input::InputManager> {
+        let mut input = None;
+        self.access("input manager", |i: &input::InputManager| input = Some(i.clone()));
+        input
+    }
+    /// Lifecycle events of the window, and closing it
+    pub fn app(&self) -> Option<app::AppManager> {
+        let mut app = None;
+        self.access("app", |a: &app::AppManager| app = Some(a.clone()));
+        app
+    }
     pub fn access<T: 'static>(&self, alias: &str,  mut function: impl FnMut(&T)) {
         if let Some(uuid) = self.scene.mod_alias.get(alias) {
             if let Some(element) = self.scene.elements.get(uuid) {
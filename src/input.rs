@@ -0,0 +1,1442 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::*;
+use crate::event::{Sender, Receiver, Locked};
+pub use glium::glutin::event::VirtualKeyCode;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    Other(u16)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    Mode,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    Other(u16)
+}
+
+// Sticks go from -1 to 1 with positive y up, triggers from 0 to 1
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+    Other(u16)
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum InputEvent {
+    KeyDown(u32),
+    KeyUp(u32),
+    // Sent after the scancode events for keys the platform recognizes
+    VirtualKeyDown(VirtualKeyCode),
+    VirtualKeyUp(VirtualKeyCode),
+    MouseDown(MouseButton),
+    MouseUp(MouseButton),
+    // Window pixels from the top left, and world units through the camera under the cursor
+    CursorMoved {
+        window: V2<f32>,
+        world: Option<V2<f32>>
+    },
+    // Lines scrolled, positive y is away from the user
+    Scroll(V2<f32>),
+    CursorEntered,
+    CursorLeft,
+    // Unicode character typed, after the keyboard layout and input method
+    Character(char),
+    GamepadConnected(usize),
+    GamepadDisconnected(usize),
+    // `player` is filled in by the InputManager from the player assignment
+    GamepadDown {
+        gamepad: usize,
+        player: Option<usize>,
+        button: GamepadButton
+    },
+    GamepadUp {
+        gamepad: usize,
+        player: Option<usize>,
+        button: GamepadButton
+    },
+    GamepadAxis {
+        gamepad: usize,
+        player: Option<usize>,
+        axis: GamepadAxis,
+        value: f32
+    }
+}
+
+
+/// Where the cursor is and which buttons are held
+#[derive(Clone, Debug, Default)]
+pub struct CursorState {
+    pub window: Option<V2<f32>>,
+    pub world: Option<V2<f32>>,
+    pub inside: bool,
+    pub held: Vec<MouseButton>
+}
+
+impl CursorState {
+    pub fn is_held(&self, button: MouseButton) -> bool {
+        self.held.contains(&button)
+    }
+}
+
+
+/// Text typed while an element has the text input focus, routed to that element only
+#[derive(Clone, Debug, PartialEq)]
+pub enum TextInputEvent {
+    // The element was given or lost the focus
+    Begin,
+    End,
+    Char(char),
+    Backspace,
+    Delete,
+    // Enter was pressed
+    Submit,
+    // Text an input method is composing, committed text comes as Char events
+    // glium's window backend only reports committed text, so this is only sent through InputManager::compose
+    Composition { text: String, cursor: Option<usize> }
+}
+
+
+/// Single key or button, as tracked by InputState
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Input {
+    Scancode(u32),
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    // Button of one gamepad
+    GamepadButton(usize, GamepadButton)
+}
+
+impl Input {
+    // Input changed by the event, and whether it is now down
+    fn from_event(event: &InputEvent) -> Option<(Self, bool)> {
+        match *event {
+            InputEvent::KeyDown(key) => Some((Input::Scancode(key), true)),
+            InputEvent::KeyUp(key) => Some((Input::Scancode(key), false)),
+            InputEvent::VirtualKeyDown(key) => Some((Input::Key(key), true)),
+            InputEvent::VirtualKeyUp(key) => Some((Input::Key(key), false)),
+            InputEvent::MouseDown(button) => Some((Input::Mouse(button), true)),
+            InputEvent::MouseUp(button) => Some((Input::Mouse(button), false)),
+            InputEvent::GamepadDown { gamepad, button, .. } => Some((Input::GamepadButton(gamepad, button), true)),
+            InputEvent::GamepadUp { gamepad, button, .. } => Some((Input::GamepadButton(gamepad, button), false)),
+            _ => None
+        }
+    }
+}
+
+
+/// What is held and what changed during the current frame
+/// Inputs pressed and released within one frame are both just pressed and just released
+#[derive(Clone, Debug, Default)]
+pub struct InputState {
+    pub frame: u64,
+    // Seconds since the first frame, from the frame time deltas
+    pub time: f32,
+    // Frame and time each held input went down
+    down: HashMap<Input, (u64, f32)>,
+    pressed: HashSet<Input>,
+    released: HashSet<Input>,
+    actions_down: HashMap<(String, Option<usize>), (u64, f32)>,
+    actions_pressed: HashSet<(String, Option<usize>)>,
+    actions_released: HashSet<(String, Option<usize>)>
+}
+
+impl InputState {
+    pub fn is_down(&self, input: Input) -> bool {
+        self.down.contains_key(&input)
+    }
+    pub fn just_pressed(&self, input: Input) -> bool {
+        self.pressed.contains(&input)
+    }
+    pub fn just_released(&self, input: Input) -> bool {
+        self.released.contains(&input)
+    }
+    /// Frame the input went down on, while it is held
+    pub fn pressed_frame(&self, input: Input) -> Option<u64> {
+        self.down.get(&input).map(|(frame, _)| *frame)
+    }
+    /// Seconds the input has been held for
+    pub fn held_time(&self, input: Input) -> Option<f32> {
+        self.down.get(&input).map(|(_, time)| self.time - time)
+    }
+
+    pub fn is_action_down(&self, action: &str, player: Option<usize>) -> bool {
+        self.actions_down.contains_key(&(action.to_string(), player))
+    }
+    pub fn action_just_pressed(&self, action: &str, player: Option<usize>) -> bool {
+        self.actions_pressed.contains(&(action.to_string(), player))
+    }
+    pub fn action_just_released(&self, action: &str, player: Option<usize>) -> bool {
+        self.actions_released.contains(&(action.to_string(), player))
+    }
+    pub fn action_held_time(&self, action: &str, player: Option<usize>) -> Option<f32> {
+        self.actions_down.get(&(action.to_string(), player)).map(|(_, time)| self.time - time)
+    }
+
+    fn input(&mut self, event: &InputEvent) {
+        if let InputEvent::GamepadDisconnected(gamepad) = *event {
+            let held: Vec<Input> = self.down.keys().filter(|i| matches!(i, Input::GamepadButton(g, _) if *g == gamepad)).copied().collect();
+            for input in held {
+                self.down.remove(&input);
+                self.released.insert(input);
+            }
+        }
+        match Input::from_event(event) {
+            // Key repeat from the OS doesn't press again
+            Some((input, true)) if !self.down.contains_key(&input) => {
+                self.down.insert(input, (self.frame, self.time));
+                self.pressed.insert(input);
+            },
+            Some((input, false)) if self.down.remove(&input).is_some() => {
+                self.released.insert(input);
+            },
+            _ => ()
+        }
+    }
+    fn action(&mut self, event: &ActionEvent) {
+        match event {
+            ActionEvent::Pressed { action, player } => {
+                self.actions_down.insert((action.clone(), *player), (self.frame, self.time));
+                self.actions_pressed.insert((action.clone(), *player));
+            },
+            ActionEvent::Released { action, player } => {
+                self.actions_down.remove(&(action.clone(), *player));
+                self.actions_released.insert((action.clone(), *player));
+            },
+            _ => ()
+        }
+    }
+    fn next_frame(&mut self, td: f32) {
+        self.frame += 1;
+        self.time += td;
+        self.pressed.clear();
+        self.released.clear();
+        self.actions_pressed.clear();
+        self.actions_released.clear();
+    }
+}
+
+
+/// Connected gamepads, which player uses each of them and the deadzones applied to their axes
+#[derive(Clone, Debug)]
+pub struct GamepadState {
+    pub connected: Vec<usize>,
+    // Gamepad used by each player, slots stay reserved when a gamepad disconnects
+    pub players: Vec<Option<usize>>,
+    // New gamepads take the first free player slot
+    pub auto_assign: bool,
+    pub deadzone: f32,
+    pub axis_deadzones: HashMap<GamepadAxis, f32>,
+    axes: HashMap<(usize, GamepadAxis), f32>
+}
+
+impl std::default::Default for GamepadState {
+    fn default() -> Self {
+        Self {
+            connected: Vec::new(),
+            players: Vec::new(),
+            auto_assign: true,
+            deadzone: 0.15,
+            axis_deadzones: HashMap::new(),
+            axes: HashMap::new()
+        }
+    }
+}
+
+impl GamepadState {
+    pub fn player(&self, gamepad: usize) -> Option<usize> {
+        self.players.iter().position(|g| *g == Some(gamepad))
+    }
+    pub fn gamepad(&self, player: usize) -> Option<usize> {
+        self.players.get(player).copied().flatten()
+    }
+    /// Last value of an axis, after the deadzone
+    pub fn axis(&self, gamepad: usize, axis: GamepadAxis) -> f32 {
+        self.axes.get(&(gamepad, axis)).copied().unwrap_or(0.0)
+    }
+
+    // Values inside the deadzone become 0, the rest is rescaled to start from 0
+    fn apply_deadzone(&self, axis: GamepadAxis, value: f32) -> f32 {
+        let deadzone = self.axis_deadzones.get(&axis).copied().unwrap_or(self.deadzone).clamp(0.0, 0.99);
+        if value.abs() <= deadzone {
+            0.0
+        } else {
+            value.signum() * ((value.abs() - deadzone) / (1.0 - deadzone)).min(1.0)
+        }
+    }
+    fn assign(&mut self, player: usize, gamepad: Option<usize>) {
+        if let Some(gamepad) = gamepad {
+            for slot in self.players.iter_mut().filter(|g| **g == Some(gamepad)) {
+                *slot = None;
+            }
+        }
+        if self.players.len() <= player {
+            self.players.resize(player + 1, None);
+        }
+        self.players[player] = gamepad;
+    }
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    // Frames since the recording started
+    pub frame: u64,
+    pub event: InputEvent
+}
+
+/// Input events of a play session, as they came in before any deadzone or player assignment
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Recording {
+    pub events: Vec<RecordedEvent>,
+    // Frames recorded, playback lasts this long even when the last frames had no input
+    pub frames: u64,
+    // Time step the session was recorded with, needed to reproduce it exactly
+    pub timestep: Option<f32>
+}
+
+impl Recording {
+    pub fn from_json(json: &str) -> Result<Self,String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid recording: {}", e))
+    }
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+    pub fn load_file(path: &str) -> Result<Self,String> {
+        let json = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        Self::from_json(&json)
+    }
+    pub fn save_file(&self, path: &str) -> Result<(),String> {
+        std::fs::write(path, self.to_json()).map_err(|e| format!("Could not write {}: {}", path, e))
+    }
+}
+
+// Recording or playing back, started at `start` frame
+#[derive(Clone, Debug)]
+enum Tape {
+    Live,
+    Recording { start: u64, recording: Recording },
+    Playing { start: u64, recording: Recording, next: usize }
+}
+
+
+/// Shared by the instance and every scene as the "input" module, whose component is a locked `Sender<InputEvent>`
+/// The manager itself is the "input manager" module from `InputManager::module`, found with `ModuleTool::input`
+#[derive(Clone)]
+pub struct InputManager {
+    sender: Sender<InputEvent>,
+    locked: Sender<InputEvent, Locked>,
+    cursor: Rc<RefCell<CursorState>>,
+    gamepads: Rc<RefCell<GamepadState>>,
+    action_sender: Sender<ActionEvent>,
+    actions: Rc<RefCell<ActionMap>>,
+    state: Rc<RefCell<InputState>>,
+    tape: Rc<RefCell<Tape>>,
+    text: Sender<TextInputEvent>,
+    text_focus: Rc<Cell<Option<Uuid>>>
+}
+
+impl std::default::Default for InputManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputManager {
+    pub fn new() -> Self {
+        let sender = Sender::new();
+        Self {
+            locked: sender.lock(),
+            sender,
+            cursor: Rc::new(RefCell::new(CursorState::default())),
+            gamepads: Rc::new(RefCell::new(GamepadState::default())),
+            action_sender: Sender::new(),
+            actions: Rc::new(RefCell::new(ActionMap::default())),
+            state: Rc::new(RefCell::new(InputState::default())),
+            tape: Rc::new(RefCell::new(Tape::Live)),
+            text: Sender::new(),
+            text_focus: Rc::new(Cell::new(None))
+        }
+    }
+    pub fn with_bindings(self, bindings: Bindings) -> Self {
+        self.set_bindings(bindings);
+        self
+    }
+    pub fn with_deadzone(self, deadzone: f32) -> Self {
+        self.set_deadzone(deadzone);
+        self
+    }
+    pub fn new_receiver(&self) -> Rc<Receiver<InputEvent>> {
+        self.sender.new_receiver()
+    }
+    pub fn sender(&self) -> Sender<InputEvent, Locked> {
+        self.locked.clone()
+    }
+    // Module for scenes, gives elements the manager itself
+    pub fn module(&self) -> InputManagerModule {
+        InputManagerModule {
+            manager: self.clone()
+        }
+    }
+    pub fn cursor(&self) -> CursorState {
+        self.cursor.borrow().clone()
+    }
+    pub fn gamepads(&self) -> GamepadState {
+        self.gamepads.borrow().clone()
+    }
+
+    /// Deadzone of every axis without one of its own
+    pub fn set_deadzone(&self, deadzone: f32) {
+        self.gamepads.borrow_mut().deadzone = deadzone;
+    }
+    pub fn set_axis_deadzone(&self, axis: GamepadAxis, deadzone: f32) {
+        self.gamepads.borrow_mut().axis_deadzones.insert(axis, deadzone);
+    }
+    /// Gives a gamepad to a player, taking it from any other player
+    pub fn assign_player(&self, player: usize, gamepad: usize) {
+        self.gamepads.borrow_mut().assign(player, Some(gamepad));
+    }
+    pub fn unassign_player(&self, player: usize) {
+        self.gamepads.borrow_mut().assign(player, None);
+    }
+    pub fn set_auto_assign(&self, auto_assign: bool) {
+        self.gamepads.borrow_mut().auto_assign = auto_assign;
+    }
+
+    /// Copy of the input state of the current frame
+    pub fn state(&self) -> InputState {
+        self.state.borrow().clone()
+    }
+    pub fn frame(&self) -> u64 {
+        self.state.borrow().frame
+    }
+    pub fn is_down(&self, input: Input) -> bool {
+        self.state.borrow().is_down(input)
+    }
+    pub fn just_pressed(&self, input: Input) -> bool {
+        self.state.borrow().just_pressed(input)
+    }
+    pub fn just_released(&self, input: Input) -> bool {
+        self.state.borrow().just_released(input)
+    }
+    pub fn action_just_pressed(&self, action: &str, player: Option<usize>) -> bool {
+        self.state.borrow().action_just_pressed(action, player)
+    }
+    pub fn action_just_released(&self, action: &str, player: Option<usize>) -> bool {
+        self.state.borrow().action_just_released(action, player)
+    }
+    /// Ends the frame, called by the instance after every element was updated
+    /// Recorded input of the new frame is sent right away during playback
+    pub fn next_frame(&self, td: f32) {
+        self.state.borrow_mut().next_frame(td);
+        self.play_frame();
+    }
+
+    /// Receiver of the text typed while `uuid` has the text input focus
+    pub fn text_receiver(&self, uuid: Uuid) -> Rc<Receiver<TextInputEvent>> {
+        self.text.new_routed_receiver(Some(uuid)).1
+    }
+    /// Gives the text input focus to an element, taking it from any other one
+    /// Key presses don't trigger actions while an element has it
+    pub fn begin_text_input(&self, uuid: Uuid) {
+        if let Some(previous) = self.text_focus.replace(Some(uuid)) {
+            if previous == uuid {
+                return;
+            }
+            self.text.route(previous, TextInputEvent::End);
+        }
+        self.text.route(uuid, TextInputEvent::Begin);
+    }
+    /// Ends text input when `uuid` has the focus
+    pub fn end_text_input(&self, uuid: Uuid) {
+        if self.text_focus.get() == Some(uuid) {
+            self.text_focus.set(None);
+            self.text.route(uuid, TextInputEvent::End);
+        }
+    }
+    pub fn text_input_focus(&self) -> Option<Uuid> {
+        self.text_focus.get()
+    }
+    /// Sends text being composed by an input method to the focused element
+    pub fn compose(&self, text: &str, cursor: Option<usize>) {
+        if let Some(focus) = self.text_focus.get() {
+            self.text.route(focus, TextInputEvent::Composition { text: text.to_string(), cursor });
+        }
+    }
+
+    /// Starts recording every input event, `timestep` is stored with the recording
+    pub fn start_recording(&self, timestep: Option<f32>) {
+        *self.tape.borrow_mut() = Tape::Recording {
+            start: self.frame(),
+            recording: Recording { timestep, ..Default::default() }
+        };
+    }
+    pub fn stop_recording(&self) -> Option<Recording> {
+        let frame = self.frame();
+        match std::mem::replace(&mut *self.tape.borrow_mut(), Tape::Live) {
+            Tape::Recording { start, mut recording } => {
+                recording.frames = frame - start + 1;
+                Some(recording)
+            },
+            tape => {
+                *self.tape.borrow_mut() = tape;
+                None
+            }
+        }
+    }
+    pub fn is_recording(&self) -> bool {
+        matches!(*self.tape.borrow(), Tape::Recording { .. })
+    }
+    /// Replaces live input with the recording from the current frame on, live input comes back when it ends
+    pub fn play(&self, recording: Recording) {
+        *self.tape.borrow_mut() = Tape::Playing { start: self.frame(), recording, next: 0 };
+        self.play_frame();
+    }
+    pub fn stop_playing(&self) {
+        if self.is_playing() {
+            *self.tape.borrow_mut() = Tape::Live;
+        }
+    }
+    pub fn is_playing(&self) -> bool {
+        matches!(*self.tape.borrow(), Tape::Playing { .. })
+    }
+    /// Time step of the recording being played back, the instance updates with it instead of its own
+    pub fn playback_timestep(&self) -> Option<f32> {
+        match &*self.tape.borrow() {
+            Tape::Playing { recording, .. } => recording.timestep,
+            _ => None
+        }
+    }
+
+    // Sends the recorded events of the current frame
+    fn play_frame(&self) {
+        let frame = self.frame();
+        let mut events = Vec::new();
+        if let Tape::Playing { start, recording, next } = &mut *self.tape.borrow_mut() {
+            while let Some(e) = recording.events.get(*next).filter(|e| start.saturating_add(e.frame) <= frame) {
+                events.push(e.event);
+                *next += 1;
+            }
+        }
+        for e in events {
+            self.process(e);
+        }
+        let finished = match &*self.tape.borrow() {
+            Tape::Playing { start, recording, next } => *next >= recording.events.len() && frame >= start + recording.frames,
+            _ => false
+        };
+        if finished {
+            *self.tape.borrow_mut() = Tape::Live;
+        }
+    }
+
+    pub fn new_action_receiver(&self) -> Rc<Receiver<ActionEvent>> {
+        self.action_sender.new_receiver()
+    }
+    pub fn bindings(&self) -> Bindings {
+        self.actions.borrow().bindings.clone()
+    }
+    /// Replaces every binding, held actions are released
+    pub fn set_bindings(&self, bindings: Bindings) {
+        *self.actions.borrow_mut() = ActionMap::new(bindings);
+    }
+    pub fn bind(&self, action: &str, binding: Binding) {
+        self.actions.borrow_mut().bind(action, binding);
+    }
+    pub fn rebind(&self, action: &str, bindings: Vec<Binding>) {
+        self.actions.borrow_mut().rebind(action, bindings);
+    }
+    /// The next key, mouse button or gamepad input becomes the only binding of the action
+    pub fn rebind_next(&self, action: &str) {
+        self.actions.borrow_mut().rebind_next(action);
+    }
+    pub fn is_action_pressed(&self, action: &str, player: Option<usize>) -> bool {
+        self.actions.borrow().is_pressed(action, player)
+    }
+    pub fn action_axis(&self, axis: &str, player: Option<usize>) -> f32 {
+        self.actions.borrow().axis(axis, player)
+    }
+
+    /// Sends every event the backend received since the last poll
+    pub fn poll_gamepads(&self, backend: &mut dyn GamepadBackend) {
+        for e in backend.poll() {
+            self.dispatch(e);
+        }
+    }
+
+    /// Updates the input state and sends the event to every receiver
+    /// Used by the instance for window events, can also inject input
+    /// Ignored while a recording is played back
+    pub fn dispatch(&self, event: InputEvent) {
+        if self.is_playing() {
+            return;
+        }
+        if let Tape::Recording { start, recording } = &mut *self.tape.borrow_mut() {
+            recording.events.push(RecordedEvent { frame: self.state.borrow().frame - *start, event });
+        }
+        self.process(event);
+    }
+
+    fn process(&self, mut event: InputEvent) {
+        {
+            let mut gamepads = self.gamepads.borrow_mut();
+            match &mut event {
+                InputEvent::GamepadConnected(gamepad) => {
+                    if !gamepads.connected.contains(gamepad) {
+                        gamepads.connected.push(*gamepad);
+                    }
+                    if gamepads.auto_assign && gamepads.player(*gamepad).is_none() {
+                        let free = gamepads.players.iter().position(|g| g.is_none()).unwrap_or(gamepads.players.len());
+                        gamepads.assign(free, Some(*gamepad));
+                    }
+                },
+                InputEvent::GamepadDisconnected(gamepad) => {
+                    let gamepad = *gamepad;
+                    gamepads.connected.retain(|g| *g != gamepad);
+                    gamepads.axes.retain(|(g, _), _| *g != gamepad);
+                },
+                InputEvent::GamepadDown { gamepad, player, .. } | InputEvent::GamepadUp { gamepad, player, .. } => {
+                    *player = gamepads.player(*gamepad);
+                },
+                InputEvent::GamepadAxis { gamepad, player, axis, value } => {
+                    *player = gamepads.player(*gamepad);
+                    *value = gamepads.apply_deadzone(*axis, *value);
+                    // Movement inside of the deadzone isn't sent
+                    if gamepads.axes.insert((*gamepad, *axis), *value).unwrap_or(0.0) == *value {
+                        return;
+                    }
+                },
+                _ => ()
+            }
+        }
+        {
+            let mut cursor = self.cursor.borrow_mut();
+            match event {
+                InputEvent::MouseDown(button) if !cursor.held.contains(&button) => cursor.held.push(button),
+                InputEvent::MouseUp(button) => cursor.held.retain(|b| *b != button),
+                InputEvent::CursorMoved { window, world } => {
+                    cursor.window = Some(window);
+                    cursor.world = world;
+                    cursor.inside = true;
+                },
+                InputEvent::CursorEntered => cursor.inside = true,
+                InputEvent::CursorLeft => cursor.inside = false,
+                _ => ()
+            }
+        }
+        self.state.borrow_mut().input(&event);
+        self.sender.send(event);
+
+        if let Some(focus) = self.text_focus.get() {
+            let text = match event {
+                InputEvent::Character('\r' | '\n') => Some(TextInputEvent::Submit),
+                InputEvent::Character('\u{8}') => Some(TextInputEvent::Backspace),
+                InputEvent::Character('\u{7f}') => Some(TextInputEvent::Delete),
+                InputEvent::Character(c) if !c.is_control() => Some(TextInputEvent::Char(c)),
+                _ => None
+            };
+            if let Some(text) = text {
+                self.text.route(focus, text);
+            }
+            // Typing doesn't trigger actions, releasing keys still ends them
+            if matches!(event, InputEvent::KeyDown(_) | InputEvent::VirtualKeyDown(_)) {
+                return;
+            }
+        }
+
+        let actions = self.actions.borrow_mut().update(&event, &self.gamepads.borrow());
+        for e in actions {
+            self.state.borrow_mut().action(&e);
+            self.action_sender.send(e);
+        }
+    }
+}
+
+impl element::ModuleBehavior for InputManager {
+    fn alias(&self) -> String {
+        "input".to_string()
+    }
+    fn component(&self) -> &dyn std::any::Any {
+        &self.locked
+    }
+}
+impl element::ElementBehavior for InputManager {
+    fn load(&self, _data: &serde_json::Map<String,serde_json::Value>) -> element::Element {
+        element::Element::new_module(self.clone())
+    }
+}
+
+#[derive(Clone)]
+pub struct InputManagerModule {
+    manager: InputManager
+}
+impl element::ModuleBehavior for InputManagerModule {
+    fn alias(&self) -> String {
+        "input manager".to_string()
+    }
+    fn component(&self) -> &dyn std::any::Any {
+        &self.manager
+    }
+}
+impl element::ElementBehavior for InputManagerModule {
+    fn load(&self, _data: &serde_json::Map<String,serde_json::Value>) -> element::Element {
+        element::Element::new_module(self.clone())
+    }
+}
+
+
+/// Source of gamepad events, polled by the instance every frame
+pub trait GamepadBackend {
+    fn poll(&mut self) -> Vec<InputEvent>;
+}
+
+/// Gamepads driven from code, for tests and on screen controls
+/// Clones share the same gamepads, so one can be given to the instance and another kept
+#[derive(Clone, Default)]
+pub struct VirtualGamepads {
+    queue: Rc<RefCell<Vec<InputEvent>>>,
+    next_id: Rc<Cell<usize>>
+}
+
+impl VirtualGamepads {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn connect(&self) -> usize {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        self.queue.borrow_mut().push(InputEvent::GamepadConnected(id));
+        id
+    }
+    pub fn disconnect(&self, gamepad: usize) {
+        self.queue.borrow_mut().push(InputEvent::GamepadDisconnected(gamepad));
+    }
+    pub fn press(&self, gamepad: usize, button: GamepadButton) {
+        self.queue.borrow_mut().push(InputEvent::GamepadDown { gamepad, player: None, button });
+    }
+    pub fn release(&self, gamepad: usize, button: GamepadButton) {
+        self.queue.borrow_mut().push(InputEvent::GamepadUp { gamepad, player: None, button });
+    }
+    pub fn set_axis(&self, gamepad: usize, axis: GamepadAxis, value: f32) {
+        self.queue.borrow_mut().push(InputEvent::GamepadAxis { gamepad, player: None, axis, value });
+    }
+}
+
+impl GamepadBackend for VirtualGamepads {
+    fn poll(&mut self) -> Vec<InputEvent> {
+        self.queue.borrow_mut().drain(..).collect()
+    }
+}
+
+
+/// Real gamepads through gilrs
+#[cfg(feature = "gilrs")]
+pub struct GilrsGamepads {
+    gilrs: gilrs::Gilrs
+}
+
+#[cfg(feature = "gilrs")]
+impl GilrsGamepads {
+    pub fn new() -> Result<Self,String> {
+        let gilrs = gilrs::Gilrs::new().map_err(|e| format!("Could not start gilrs: {}", e))?;
+        Ok(Self { gilrs })
+    }
+    fn button(button: gilrs::Button) -> GamepadButton {
+        use gilrs::Button as B;
+        match button {
+            B::South => GamepadButton::South,
+            B::East => GamepadButton::East,
+            B::North => GamepadButton::North,
+            B::West => GamepadButton::West,
+            B::LeftTrigger => GamepadButton::LeftBumper,
+            B::RightTrigger => GamepadButton::RightBumper,
+            B::LeftTrigger2 => GamepadButton::LeftTrigger,
+            B::RightTrigger2 => GamepadButton::RightTrigger,
+            B::Select => GamepadButton::Select,
+            B::Start => GamepadButton::Start,
+            B::Mode => GamepadButton::Mode,
+            B::LeftThumb => GamepadButton::LeftStick,
+            B::RightThumb => GamepadButton::RightStick,
+            B::DPadUp => GamepadButton::DPadUp,
+            B::DPadDown => GamepadButton::DPadDown,
+            B::DPadLeft => GamepadButton::DPadLeft,
+            B::DPadRight => GamepadButton::DPadRight,
+            other => GamepadButton::Other(other as u16)
+        }
+    }
+    fn axis(axis: gilrs::Axis) -> GamepadAxis {
+        use gilrs::Axis as A;
+        match axis {
+            A::LeftStickX => GamepadAxis::LeftStickX,
+            A::LeftStickY => GamepadAxis::LeftStickY,
+            A::RightStickX => GamepadAxis::RightStickX,
+            A::RightStickY => GamepadAxis::RightStickY,
+            A::LeftZ => GamepadAxis::LeftTrigger,
+            A::RightZ => GamepadAxis::RightTrigger,
+            other => GamepadAxis::Other(other as u16)
+        }
+    }
+}
+
+#[cfg(feature = "gilrs")]
+impl GamepadBackend for GilrsGamepads {
+    fn poll(&mut self) -> Vec<InputEvent> {
+        use gilrs::EventType;
+        let mut events = Vec::new();
+        while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+            let gamepad: usize = id.into();
+            match event {
+                EventType::Connected => events.push(InputEvent::GamepadConnected(gamepad)),
+                EventType::Disconnected => events.push(InputEvent::GamepadDisconnected(gamepad)),
+                EventType::ButtonPressed(button, _) => events.push(InputEvent::GamepadDown { gamepad, player: None, button: Self::button(button) }),
+                EventType::ButtonReleased(button, _) => events.push(InputEvent::GamepadUp { gamepad, player: None, button: Self::button(button) }),
+                EventType::AxisChanged(axis, value, _) => events.push(InputEvent::GamepadAxis { gamepad, player: None, axis: Self::axis(axis), value }),
+                // Most gamepads report analog triggers as buttons
+                EventType::ButtonChanged(gilrs::Button::LeftTrigger2, value, _) => events.push(InputEvent::GamepadAxis { gamepad, player: None, axis: GamepadAxis::LeftTrigger, value }),
+                EventType::ButtonChanged(gilrs::Button::RightTrigger2, value, _) => events.push(InputEvent::GamepadAxis { gamepad, player: None, axis: GamepadAxis::RightTrigger, value }),
+                _ => ()
+            }
+        }
+        events
+    }
+}
+
+/// gilrs when the feature is enabled and it starts, virtual gamepads otherwise
+pub fn default_gamepads() -> Box<dyn GamepadBackend> {
+    #[cfg(feature = "gilrs")]
+    match GilrsGamepads::new() {
+        Ok(gilrs) => return Box::new(gilrs),
+        Err(e) => println!("{}", e)
+    }
+    Box::new(VirtualGamepads::new())
+}
+
+
+/// Input that can trigger an action
+/// In JSON: `{"key": "Space"}`, `{"scancode": 57}`, `{"mouse": "Left"}`, `{"gamepad_button": "South"}`
+/// or `{"gamepad_axis": {"axis": "LeftStickX", "threshold": -0.5}}`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Binding {
+    Scancode(u32),
+    // Independent of the keyboard layout, saved by name
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    GamepadButton(GamepadButton),
+    // Held while the axis is past the threshold, negative thresholds are held below it
+    GamepadAxis {
+        axis: GamepadAxis,
+        threshold: f32
+    }
+}
+
+impl Binding {
+    pub fn key(key: VirtualKeyCode) -> Self {
+        Binding::Key(key)
+    }
+
+    /// Whether the binding is held after the event, None when the event doesn't concern it
+    pub fn matches(&self, event: &InputEvent) -> Option<bool> {
+        match (self, *event) {
+            (Binding::Scancode(s), InputEvent::KeyDown(key)) if *s == key => Some(true),
+            (Binding::Scancode(s), InputEvent::KeyUp(key)) if *s == key => Some(false),
+            (Binding::Key(k), InputEvent::VirtualKeyDown(key)) if *k == key => Some(true),
+            (Binding::Key(k), InputEvent::VirtualKeyUp(key)) if *k == key => Some(false),
+            (Binding::Mouse(b), InputEvent::MouseDown(button)) if *b == button => Some(true),
+            (Binding::Mouse(b), InputEvent::MouseUp(button)) if *b == button => Some(false),
+            (Binding::GamepadButton(b), InputEvent::GamepadDown { button, .. }) if *b == button => Some(true),
+            (Binding::GamepadButton(b), InputEvent::GamepadUp { button, .. }) if *b == button => Some(false),
+            (Binding::GamepadAxis { axis: a, threshold }, InputEvent::GamepadAxis { axis, value, .. }) if *a == axis => {
+                Some(if *threshold < 0.0 { value <= *threshold } else { value >= *threshold })
+            },
+            _ => None
+        }
+    }
+
+    // Binding for the input pressed by the event, used when rebinding to the next input
+    fn from_event(event: &InputEvent) -> Option<Self> {
+        match *event {
+            InputEvent::VirtualKeyDown(key) => Some(Binding::key(key)),
+            InputEvent::MouseDown(button) => Some(Binding::Mouse(button)),
+            InputEvent::GamepadDown { button, .. } => Some(Binding::GamepadButton(button)),
+            InputEvent::GamepadAxis { axis, value, .. } if value.abs() >= 0.5 => Some(Binding::GamepadAxis { axis, threshold: value.signum() * 0.5 }),
+            _ => None
+        }
+    }
+}
+
+
+/// Axis made of bindings pulling it each way and analog gamepad axes
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AxisBinding {
+    pub negative: Vec<Binding>,
+    pub positive: Vec<Binding>,
+    pub analog: Vec<GamepadAxis>
+}
+
+impl AxisBinding {
+    pub fn new(negative: Vec<Binding>, positive: Vec<Binding>) -> Self {
+        Self {
+            negative,
+            positive,
+            analog: Vec::new()
+        }
+    }
+    pub fn with_analog(mut self, axis: GamepadAxis) -> Self {
+        self.analog.push(axis);
+        self
+    }
+}
+
+
+/// Named actions and axes with the inputs bound to them
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bindings {
+    pub actions: HashMap<String, Vec<Binding>>,
+    pub axes: HashMap<String, AxisBinding>
+}
+
+impl Bindings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_action(mut self, action: &str, bindings: Vec<Binding>) -> Self {
+        self.actions.insert(action.to_string(), bindings);
+        self
+    }
+    pub fn with_axis(mut self, axis: &str, binding: AxisBinding) -> Self {
+        self.axes.insert(axis.to_string(), binding);
+        self
+    }
+
+    pub fn from_json(json: &str) -> Result<Self,String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid bindings: {}", e))
+    }
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+    pub fn load_file(path: &str) -> Result<Self,String> {
+        let json = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        Self::from_json(&json)
+    }
+    pub fn save_file(&self, path: &str) -> Result<(),String> {
+        std::fs::write(path, self.to_json()).map_err(|e| format!("Could not write {}: {}", path, e))
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ActionEvent {
+    // Keyboard and mouse input has no player
+    Pressed { action: String, player: Option<usize> },
+    Released { action: String, player: Option<usize> },
+    Axis { axis: String, value: f32, player: Option<usize> },
+    // The action was bound to the input captured by InputManager::rebind_next
+    Rebound { action: String, binding: Binding }
+}
+
+
+// Binding held, by its index and the gamepad holding it
+type Held = HashSet<(usize, Option<usize>)>;
+
+/// Turns input events into action events
+#[derive(Clone, Debug, Default)]
+pub struct ActionMap {
+    pub bindings: Bindings,
+    actions: HashMap<String, Held>,
+    axes: HashMap<(String, bool), Held>,
+    pressed: HashSet<(String, Option<usize>)>,
+    axis_values: HashMap<(String, Option<usize>), f32>,
+    // Action bound to the next pressed input
+    capture: Option<String>
+}
+
+impl ActionMap {
+    pub fn new(bindings: Bindings) -> Self {
+        Self {
+            bindings,
+            ..Default::default()
+        }
+    }
+    pub fn is_pressed(&self, action: &str, player: Option<usize>) -> bool {
+        self.pressed.contains(&(action.to_string(), player))
+    }
+    /// Pressed by the keyboard and mouse or by any player
+    pub fn any_pressed(&self, action: &str) -> bool {
+        self.pressed.iter().any(|(a, _)| a == action)
+    }
+    pub fn axis(&self, axis: &str, player: Option<usize>) -> f32 {
+        self.axis_values.get(&(axis.to_string(), player)).copied().unwrap_or(0.0)
+    }
+
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        self.bindings.actions.entry(action.to_string()).or_default().push(binding);
+    }
+    /// Replaces the bindings of an action, releasing it
+    pub fn rebind(&mut self, action: &str, bindings: Vec<Binding>) {
+        self.bindings.actions.insert(action.to_string(), bindings);
+        self.actions.remove(action);
+    }
+    pub fn rebind_next(&mut self, action: &str) {
+        self.capture = Some(action.to_string());
+    }
+
+    /// Action events caused by an input event, `gamepads` gives the player of each gamepad
+    pub fn update(&mut self, event: &InputEvent, gamepads: &GamepadState) -> Vec<ActionEvent> {
+        let mut events = Vec::new();
+
+        if let Some(action) = self.capture.clone() {
+            if let Some(binding) = Binding::from_event(event) {
+                self.capture = None;
+                self.rebind(&action, vec![binding.clone()]);
+                events.push(ActionEvent::Rebound { action, binding });
+                // The input that was captured doesn't press anything
+                return events;
+            }
+        }
+
+        let gamepad = match *event {
+            InputEvent::GamepadDown { gamepad, .. } | InputEvent::GamepadUp { gamepad, .. } | InputEvent::GamepadAxis { gamepad, .. } => Some(gamepad),
+            InputEvent::GamepadDisconnected(gamepad) => {
+                for held in self.actions.values_mut().chain(self.axes.values_mut()) {
+                    held.retain(|(_, g)| *g != Some(gamepad));
+                }
+                None
+            },
+            _ => None
+        };
+        for (action, bindings) in &self.bindings.actions {
+            update_held(self.actions.entry(action.clone()).or_default(), bindings, event, gamepad);
+        }
+        for (axis, binding) in &self.bindings.axes {
+            update_held(self.axes.entry((axis.clone(), false)).or_default(), &binding.negative, event, gamepad);
+            update_held(self.axes.entry((axis.clone(), true)).or_default(), &binding.positive, event, gamepad);
+        }
+
+        // Every player that could have been affected by the event
+        let mut players = vec![None];
+        players.extend(gamepads.players.iter().enumerate().map(|(p, _)| Some(p)));
+        let player_of = |g: &Option<usize>| g.and_then(|g| gamepads.player(g));
+
+        let mut actions: Vec<&String> = self.bindings.actions.keys().collect();
+        actions.sort();
+        for action in actions {
+            let held = self.actions.get(action);
+            for player in &players {
+                let down = held.is_some_and(|h| h.iter().any(|(_, g)| player_of(g) == *player));
+                let key = (action.clone(), *player);
+                if down && self.pressed.insert(key.clone()) {
+                    events.push(ActionEvent::Pressed { action: action.clone(), player: *player });
+                } else if !down && self.pressed.remove(&key) {
+                    events.push(ActionEvent::Released { action: action.clone(), player: *player });
+                }
+            }
+        }
+
+        let mut axes: Vec<&String> = self.bindings.axes.keys().collect();
+        axes.sort();
+        for axis in axes {
+            let binding = &self.bindings.axes[axis];
+            for player in &players {
+                let pulled = |positive: bool| self.axes.get(&(axis.clone(), positive))
+                    .is_some_and(|h| h.iter().any(|(_, g)| player_of(g) == *player)) as i32 as f32;
+                let analog: f32 = player.and_then(|p| gamepads.gamepad(p))
+                    .map(|g| binding.analog.iter().map(|a| gamepads.axis(g, *a)).sum())
+                    .unwrap_or(0.0);
+                let value = (pulled(true) - pulled(false) + analog).clamp(-1.0, 1.0);
+                if self.axis_values.insert((axis.clone(), *player), value).unwrap_or(0.0) != value {
+                    events.push(ActionEvent::Axis { axis: axis.clone(), value, player: *player });
+                }
+            }
+        }
+        events
+    }
+}
+
+fn update_held(held: &mut Held, bindings: &[Binding], event: &InputEvent, gamepad: Option<usize>) {
+    for (idx, binding) in bindings.iter().enumerate() {
+        match binding.matches(event) {
+            Some(true) => { held.insert((idx, gamepad)); },
+            Some(false) => { held.remove(&(idx, gamepad)); },
+            None => ()
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Manager fed by virtual gamepads, polled like the instance does every frame
+    fn setup() -> (InputManager, VirtualGamepads) {
+        (InputManager::new(), VirtualGamepads::new())
+    }
+    fn poll(input: &InputManager, pads: &VirtualGamepads) {
+        input.poll_gamepads(&mut pads.clone());
+    }
+
+    #[test]
+    fn connecting_assigns_players() {
+        let (input, pads) = setup();
+        let (a, b) = (pads.connect(), pads.connect());
+        poll(&input, &pads);
+        let state = input.gamepads();
+        assert_eq!(state.connected, vec![a, b]);
+        assert_eq!((state.player(a), state.player(b)), (Some(0), Some(1)));
+
+        // The slot stays reserved until the gamepad comes back
+        pads.disconnect(a);
+        let c = pads.connect();
+        poll(&input, &pads);
+        assert_eq!(input.gamepads().connected, vec![b, c]);
+        assert_eq!(input.gamepads().player(c), Some(2));
+
+        input.assign_player(0, c);
+        assert_eq!(input.gamepads().gamepad(0), Some(c));
+        assert_eq!(input.gamepads().player(c), Some(0));
+
+        let (input, pads) = setup();
+        input.set_auto_assign(false);
+        let d = pads.connect();
+        poll(&input, &pads);
+        assert_eq!(input.gamepads().player(d), None);
+    }
+
+    #[test]
+    fn buttons_are_held_per_gamepad() {
+        let (input, pads) = setup();
+        let receiver = input.new_receiver();
+        let g = pads.connect();
+        pads.press(g, GamepadButton::South);
+        poll(&input, &pads);
+        assert!(receiver.poll().iter().any(|e| matches!(e, InputEvent::GamepadDown { player: Some(0), button: GamepadButton::South, .. })));
+        assert!(input.is_down(Input::GamepadButton(g, GamepadButton::South)));
+        assert!(input.just_pressed(Input::GamepadButton(g, GamepadButton::South)));
+        assert!(!input.is_down(Input::GamepadButton(g + 1, GamepadButton::South)));
+
+        input.next_frame(0.1);
+        pads.release(g, GamepadButton::South);
+        poll(&input, &pads);
+        assert!(!input.is_down(Input::GamepadButton(g, GamepadButton::South)));
+        assert!(input.just_released(Input::GamepadButton(g, GamepadButton::South)));
+    }
+
+    #[test]
+    fn axes_apply_the_deadzone() {
+        let (input, pads) = setup();
+        input.set_deadzone(0.2);
+        input.set_axis_deadzone(GamepadAxis::LeftTrigger, 0.5);
+        let receiver = input.new_receiver();
+        let g = pads.connect();
+        poll(&input, &pads);
+        receiver.poll();
+
+        // Inside of the deadzone nothing is sent
+        pads.set_axis(g, GamepadAxis::LeftStickX, 0.1);
+        pads.set_axis(g, GamepadAxis::LeftTrigger, 0.4);
+        poll(&input, &pads);
+        assert!(receiver.poll().is_empty());
+        assert_eq!(input.gamepads().axis(g, GamepadAxis::LeftStickX), 0.0);
+
+        // The rest is rescaled to start from the deadzone
+        pads.set_axis(g, GamepadAxis::LeftStickX, -0.6);
+        pads.set_axis(g, GamepadAxis::LeftTrigger, 1.0);
+        poll(&input, &pads);
+        assert_eq!(receiver.poll().len(), 2);
+        assert!((input.gamepads().axis(g, GamepadAxis::LeftStickX) + 0.5).abs() < 1e-5);
+        assert_eq!(input.gamepads().axis(g, GamepadAxis::LeftTrigger), 1.0);
+
+        // Disconnecting forgets the axes
+        pads.disconnect(g);
+        poll(&input, &pads);
+        assert_eq!(input.gamepads().axis(g, GamepadAxis::LeftStickX), 0.0);
+    }
+
+    #[test]
+    fn actions_bind_to_gamepad_input() {
+        let bindings = Bindings::new()
+            .with_action("jump", vec![Binding::key(VirtualKeyCode::Space), Binding::GamepadButton(GamepadButton::South)])
+            .with_action("throttle", vec![Binding::GamepadAxis { axis: GamepadAxis::RightTrigger, threshold: 0.5 }])
+            .with_axis("move", AxisBinding::new(
+                vec![Binding::GamepadButton(GamepadButton::DPadLeft)],
+                vec![Binding::GamepadButton(GamepadButton::DPadRight)]
+            ).with_analog(GamepadAxis::LeftStickX));
+        let (input, pads) = setup();
+        let input = input.with_bindings(bindings).with_deadzone(0.0);
+        let (a, b) = (pads.connect(), pads.connect());
+
+        pads.press(b, GamepadButton::South);
+        poll(&input, &pads);
+        assert!(input.is_action_pressed("jump", Some(1)));
+        assert!(!input.is_action_pressed("jump", Some(0)));
+        assert!(!input.is_action_pressed("jump", None));
+        assert!(input.state().action_just_pressed("jump", Some(1)));
+
+        pads.set_axis(a, GamepadAxis::RightTrigger, 0.4);
+        poll(&input, &pads);
+        assert!(!input.is_action_pressed("throttle", Some(0)));
+        pads.set_axis(a, GamepadAxis::RightTrigger, 0.7);
+        poll(&input, &pads);
+        assert!(input.is_action_pressed("throttle", Some(0)));
+
+        pads.press(a, GamepadButton::DPadLeft);
+        pads.set_axis(b, GamepadAxis::LeftStickX, 0.25);
+        poll(&input, &pads);
+        assert_eq!(input.action_axis("move", Some(0)), -1.0);
+        assert_eq!(input.action_axis("move", Some(1)), 0.25);
+
+        // Held actions are released when their gamepad goes away
+        pads.disconnect(b);
+        poll(&input, &pads);
+        assert!(!input.is_action_pressed("jump", Some(1)));
+
+        input.rebind_next("jump");
+        pads.press(a, GamepadButton::North);
+        poll(&input, &pads);
+        assert_eq!(input.bindings().actions["jump"], vec![Binding::GamepadButton(GamepadButton::North)]);
+        assert!(!input.is_action_pressed("jump", Some(0)));
+    }
+
+    #[test]
+    fn actions_bind_to_keys_and_mouse() {
+        let mut map = ActionMap::new(Bindings::new()
+            .with_action("jump", vec![Binding::key(VirtualKeyCode::Space), Binding::Scancode(57)])
+            .with_action("fire", vec![Binding::Mouse(MouseButton::Left)])
+            .with_axis("move", AxisBinding::new(vec![Binding::key(VirtualKeyCode::A)], vec![Binding::key(VirtualKeyCode::D)])));
+        let pads = GamepadState::default();
+
+        assert_eq!(map.update(&InputEvent::VirtualKeyDown(VirtualKeyCode::Space), &pads), vec![ActionEvent::Pressed { action: "jump".to_string(), player: None }]);
+        // Held by both bindings, released once neither is
+        assert!(map.update(&InputEvent::KeyDown(57), &pads).is_empty());
+        assert!(map.update(&InputEvent::VirtualKeyUp(VirtualKeyCode::Space), &pads).is_empty());
+        assert!(map.is_pressed("jump", None));
+        assert_eq!(map.update(&InputEvent::KeyUp(57), &pads), vec![ActionEvent::Released { action: "jump".to_string(), player: None }]);
+        // Other keys leave it alone
+        assert!(map.update(&InputEvent::VirtualKeyDown(VirtualKeyCode::Return), &pads).is_empty());
+
+        map.update(&InputEvent::MouseDown(MouseButton::Left), &pads);
+        assert!(map.is_pressed("fire", None));
+        map.update(&InputEvent::MouseDown(MouseButton::Right), &pads);
+        map.update(&InputEvent::MouseUp(MouseButton::Left), &pads);
+        assert!(!map.is_pressed("fire", None));
+
+        map.update(&InputEvent::VirtualKeyDown(VirtualKeyCode::A), &pads);
+        assert_eq!(map.axis("move", None), -1.0);
+        map.update(&InputEvent::VirtualKeyDown(VirtualKeyCode::D), &pads);
+        assert_eq!(map.axis("move", None), 0.0);
+    }
+
+    #[test]
+    fn bindings_round_trip_through_json() {
+        let bindings = Bindings::new()
+            .with_action("jump", vec![Binding::key(VirtualKeyCode::Space), Binding::Mouse(MouseButton::Other(4))])
+            .with_action("throttle", vec![Binding::GamepadAxis { axis: GamepadAxis::RightTrigger, threshold: -0.5 }])
+            .with_axis("move", AxisBinding::new(vec![Binding::Scancode(30)], vec![Binding::GamepadButton(GamepadButton::DPadRight)]).with_analog(GamepadAxis::LeftStickX));
+        let json = bindings.to_json();
+        assert_eq!(Bindings::from_json(&json).unwrap(), bindings);
+
+        // Keys are saved by name
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["actions"]["jump"][0], serde_json::json!({"key": "Space"}));
+        let parsed = Bindings::from_json(r#"{"actions": {"left": [{"key": "Left"}, {"scancode": 75}]}}"#).unwrap();
+        assert_eq!(parsed.actions["left"], vec![Binding::key(VirtualKeyCode::Left), Binding::Scancode(75)]);
+        assert!(Bindings::from_json(r#"{"actions": {"left": [{"key": "NotAKey"}]}}"#).is_err());
+    }
+
+    #[test]
+    fn rebinding_captures_the_next_press() {
+        let mut map = ActionMap::new(Bindings::new().with_action("jump", vec![Binding::key(VirtualKeyCode::Space)]));
+        let pads = GamepadState::default();
+        map.rebind_next("jump");
+
+        // Releases and movement aren't captured
+        assert!(map.update(&InputEvent::VirtualKeyUp(VirtualKeyCode::Space), &pads).is_empty());
+        assert!(map.update(&InputEvent::CursorMoved { window: [1.0, 1.0].into(), world: None }, &pads).is_empty());
+
+        let events = map.update(&InputEvent::VirtualKeyDown(VirtualKeyCode::J), &pads);
+        assert_eq!(events, vec![ActionEvent::Rebound { action: "jump".to_string(), binding: Binding::key(VirtualKeyCode::J) }]);
+        assert!(!map.is_pressed("jump", None));
+        assert_eq!(map.bindings.actions["jump"], vec![Binding::key(VirtualKeyCode::J)]);
+
+        // Only the new binding presses it
+        assert!(map.update(&InputEvent::VirtualKeyDown(VirtualKeyCode::Space), &pads).is_empty());
+        map.update(&InputEvent::VirtualKeyUp(VirtualKeyCode::J), &pads);
+        assert_eq!(map.update(&InputEvent::VirtualKeyDown(VirtualKeyCode::J), &pads), vec![ActionEvent::Pressed { action: "jump".to_string(), player: None }]);
+    }
+
+    #[test]
+    fn presses_last_one_frame() {
+        let (input, _) = setup();
+        let space = Input::Key(VirtualKeyCode::Space);
+        input.dispatch(InputEvent::VirtualKeyDown(VirtualKeyCode::Space));
+        assert!(input.is_down(space) && input.just_pressed(space));
+        assert_eq!(input.state().pressed_frame(space), Some(0));
+
+        input.next_frame(0.5);
+        assert!(input.is_down(space));
+        assert!(!input.just_pressed(space));
+        assert_eq!(input.state().held_time(space), Some(0.5));
+
+        input.dispatch(InputEvent::VirtualKeyUp(VirtualKeyCode::Space));
+        assert!(!input.is_down(space) && input.just_released(space));
+        input.next_frame(0.5);
+        assert!(!input.just_released(space));
+        assert_eq!(input.state().pressed_frame(space), None);
+    }
+
+    #[test]
+    fn key_repeat_doesnt_press_again() {
+        let (input, _) = setup();
+        let a = Input::Key(VirtualKeyCode::A);
+        input.dispatch(InputEvent::VirtualKeyDown(VirtualKeyCode::A));
+        input.next_frame(0.1);
+        input.dispatch(InputEvent::VirtualKeyDown(VirtualKeyCode::A));
+        input.dispatch(InputEvent::KeyDown(30));
+        input.dispatch(InputEvent::KeyDown(30));
+        assert!(!input.just_pressed(a));
+        assert_eq!(input.state().pressed_frame(a), Some(0));
+        assert!(input.just_pressed(Input::Scancode(30)));
+
+        // Releasing something that isn't held does nothing
+        input.dispatch(InputEvent::MouseUp(MouseButton::Left));
+        assert!(!input.just_released(Input::Mouse(MouseButton::Left)));
+    }
+
+    #[test]
+    fn taps_within_a_frame_are_pressed_and_released() {
+        let (input, _) = setup();
+        let left = Input::Mouse(MouseButton::Left);
+        input.dispatch(InputEvent::MouseDown(MouseButton::Left));
+        input.dispatch(InputEvent::MouseUp(MouseButton::Left));
+        assert!(input.just_pressed(left) && input.just_released(left));
+        assert!(!input.is_down(left));
+        input.next_frame(0.1);
+        assert!(!input.just_pressed(left) && !input.just_released(left));
+    }
+
+    #[test]
+    fn tracks_the_cursor() {
+        let (input, _) = setup();
+        let receiver = input.new_receiver();
+        assert!(input.cursor().window.is_none());
+        assert!(!input.cursor().inside);
+
+        input.dispatch(InputEvent::CursorMoved { window: [10.0, 20.0].into(), world: Some([1.0, 2.0].into()) });
+        let cursor = input.cursor();
+        assert_eq!(cursor.window.map(|p| (p.x, p.y)), Some((10.0, 20.0)));
+        assert_eq!(cursor.world.map(|p| (p.x, p.y)), Some((1.0, 2.0)));
+        assert!(cursor.inside);
+
+        input.dispatch(InputEvent::MouseDown(MouseButton::Left));
+        input.dispatch(InputEvent::MouseDown(MouseButton::Left));
+        input.dispatch(InputEvent::MouseDown(MouseButton::Right));
+        input.dispatch(InputEvent::MouseUp(MouseButton::Right));
+        assert_eq!(input.cursor().held, vec![MouseButton::Left]);
+        assert!(input.cursor().is_held(MouseButton::Left));
+
+        // Leaving keeps the last position
+        input.dispatch(InputEvent::CursorLeft);
+        assert!(!input.cursor().inside);
+        assert_eq!(input.cursor().window.map(|p| (p.x, p.y)), Some((10.0, 20.0)));
+        input.dispatch(InputEvent::CursorEntered);
+        assert!(input.cursor().inside);
+        assert_eq!(receiver.poll().len(), 7);
+    }
+
+    #[test]
+    fn routes_text_to_the_focused_element() {
+        let (input, _) = setup();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let (text_a, text_b) = (input.text_receiver(a), input.text_receiver(b));
+
+        // Nothing is sent without a focus
+        input.dispatch(InputEvent::Character('x'));
+        assert!(text_a.poll().is_empty() && text_b.poll().is_empty());
+
+        input.begin_text_input(a);
+        for c in ['h', 'i', '\u{8}', '\r', '\t'] {
+            input.dispatch(InputEvent::Character(c));
+        }
+        input.compose("ka", Some(2));
+        assert_eq!(text_a.poll(), vec![
+            TextInputEvent::Begin,
+            TextInputEvent::Char('h'),
+            TextInputEvent::Char('i'),
+            TextInputEvent::Backspace,
+            TextInputEvent::Submit,
+            TextInputEvent::Composition { text: "ka".to_string(), cursor: Some(2) }
+        ]);
+        assert!(text_b.poll().is_empty());
+
+        // Taking the focus ends it for the other element
+        input.begin_text_input(b);
+        input.dispatch(InputEvent::Character('y'));
+        assert_eq!(text_a.poll(), vec![TextInputEvent::End]);
+        assert_eq!(text_b.poll(), vec![TextInputEvent::Begin, TextInputEvent::Char('y')]);
+
+        input.end_text_input(a);
+        assert_eq!(input.text_input_focus(), Some(b));
+        input.end_text_input(b);
+        assert_eq!(input.text_input_focus(), None);
+        assert_eq!(text_b.poll(), vec![TextInputEvent::End]);
+    }
+
+    #[test]
+    fn typing_doesnt_trigger_actions() {
+        let (input, _) = setup();
+        input.rebind("jump", vec![Binding::key(VirtualKeyCode::Space)]);
+        let actions = input.new_action_receiver();
+        input.dispatch(InputEvent::VirtualKeyDown(VirtualKeyCode::Space));
+        assert!(input.is_action_pressed("jump", None));
+
+        // Held actions still end while typing
+        let field = Uuid::new_v4();
+        input.begin_text_input(field);
+        input.dispatch(InputEvent::VirtualKeyUp(VirtualKeyCode::Space));
+        input.dispatch(InputEvent::VirtualKeyDown(VirtualKeyCode::Space));
+        assert!(!input.is_action_pressed("jump", None));
+        // Raw input is still tracked
+        assert!(input.is_down(Input::Key(VirtualKeyCode::Space)));
+
+        input.end_text_input(field);
+        input.dispatch(InputEvent::VirtualKeyUp(VirtualKeyCode::Space));
+        input.dispatch(InputEvent::VirtualKeyDown(VirtualKeyCode::Space));
+        assert!(input.is_action_pressed("jump", None));
+        assert_eq!(actions.poll(), vec![
+            ActionEvent::Pressed { action: "jump".to_string(), player: None },
+            ActionEvent::Released { action: "jump".to_string(), player: None },
+            ActionEvent::Pressed { action: "jump".to_string(), player: None }
+        ]);
+    }
+}
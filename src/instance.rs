@@ -1,37 +1,11 @@
-use std::{ops::Deref, path, time::{self, Duration, Instant}};
-use crate::{element, event::Sender, scene::SceneManager, Vertex};
-use std::path::Path;
-use glium::{glutin::{dpi::PhysicalSize, event::{ElementState, KeyboardInput}, event_loop::ControlFlow}, implement_vertex, texture, Surface, Texture2d};
-use std::collections::HashMap;
-
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::time::{Duration, Instant};
+use crate::{V2, scene::SceneManager};
+use glium::{glutin::{dpi::PhysicalSize, event::{ElementState, KeyboardInput, MouseScrollDelta}, event_loop::ControlFlow}, Surface};
 use crate::sprite;
 
 
-#[derive(Clone, Copy)]
-pub enum InputEvent {
-    KeyDown(u32),
-    KeyUp(u32)
-}
-#[derive(Clone)]
-pub struct InputManager {
-    sender: Sender<InputEvent, crate::event::Locked>
-}
-
-impl element::ModuleBehavior for InputManager {
-    fn alias(&self) -> String {
-        "input".to_string()
-    }
-    fn component(&self) -> &dyn std::any::Any {
-        &self.sender
-    }
-}
-impl element::ElementBehavior for InputManager {
-    fn load(&self, data: &serde_json::Map<String,serde_json::Value>) -> element::Element {
-        element::Element::new_module(self.clone())
-    }
-}
+pub use crate::input::{InputEvent, InputManager, MouseButton, CursorState, GamepadButton, GamepadAxis, ActionEvent, Binding, Bindings, Input, InputState, Recording, TextInputEvent};
+pub use crate::app::{AppEvent, AppManager, FrameStats};
 
 
 pub struct Instance {
@@ -39,14 +13,31 @@ pub struct Instance {
 
     pub bg_color: (f32,f32,f32,f32),
     pub aspect_ratio: f32,
+    // Title, size, mode and cursor the window is created with
+    pub window: crate::window::WindowConfig,
+    // Starts in borderless fullscreen when the window mode is left windowed
+    #[deprecated(note = "set `window.mode` instead")]
     pub fullscreen: bool,
     pub sprite_sheet: sprite::SpriteSheet,
     map_editor_ss: sprite::SpriteSheet,
     pub ss_path: &'static str,
-
-
-    input: Sender<InputEvent>,
-    input_manager: InputManager
+    pub post_process: crate::post_process::PostProcess,
+    // Renders at a virtual resolution scaled up by whole numbers when set
+    pub pixel_perfect: Option<crate::render::PixelPerfect>,
+
+
+    // Frames per second, unlimited when not set
+    pub frame_rate: Option<f32>,
+    // Frame rate while the window isn't focused, frame_rate is used when not set
+    pub unfocused_frame_rate: Option<f32>,
+    // Every update uses this time step when set, as many times as needed to keep up with real time
+    pub fixed_timestep: Option<f32>,
+    // Polled for gamepad events every frame
+    pub gamepads: Box<dyn crate::input::GamepadBackend>,
+
+    input_manager: InputManager,
+    window_manager: crate::window::WindowManager,
+    app_manager: crate::app::AppManager
 }
 impl std::default::Default for Instance {
     fn default() -> Self {
@@ -55,38 +46,114 @@ impl std::default::Default for Instance {
 }
 
 impl Instance {
+    #[allow(deprecated)]
     pub fn new() -> Self {
-        let i = Sender::new();
         Self {
             scene_manager: SceneManager::new(),
             bg_color: (0.0,0.0,0.0,1.0),
             aspect_ratio: 16.0/9.0,
 
+            window: crate::window::WindowConfig::default(),
             fullscreen: false,
             sprite_sheet: sprite::SpriteSheet::new((8,8)),
             map_editor_ss: sprite::SpriteSheet::new((8,8)),
             ss_path: "",
-
-            input_manager: InputManager {
-                sender: i.lock()
-            },
-            input: i
+            post_process: crate::post_process::PostProcess::new(),
+            pixel_perfect: None,
+
+            frame_rate: Some(60.0),
+            unfocused_frame_rate: Some(15.0),
+            fixed_timestep: None,
+            gamepads: crate::input::default_gamepads(),
+            input_manager: InputManager::new(),
+            window_manager: crate::window::WindowManager::new(),
+            app_manager: crate::app::AppManager::new()
         }
     }
     pub fn scene_manager(&mut self) -> &mut SceneManager {
         &mut self.scene_manager
     }
+    /// Loads frame names into the sprite sheet and resolves scene data through them, call before creating scenes
+    pub fn load_frames(&mut self, data: &serde_json::Value) -> Result<(),String> {
+        self.sprite_sheet.load_frames(data)?;
+        self.scene_manager.json_manager.set_frames(&self.sprite_sheet);
+        Ok(())
+    }
     pub fn input(&self) -> InputManager{
         self.input_manager.clone()
     }
+    /// Module elements change the window through, its config is `window` once started
+    pub fn window_manager(&self) -> crate::window::WindowManager {
+        self.window_manager.clone()
+    }
+    /// Module elements get lifecycle events from, and can cancel closing through
+    pub fn app_manager(&self) -> crate::app::AppManager {
+        self.app_manager.clone()
+    }
+    /// Registers the shared modules and initializes every scene, done by start
+    /// Call it before stepping an instance that never opens a window
+    pub fn init_scenes(&mut self) {
+        use crate::element::Element;
+        let post_process = self.post_process.manager();
+        for (_, scene) in self.scene_manager.scenes.iter_mut() {
+            // Scenes can be given their own modules under these aliases before starting
+            if !scene.mod_alias.contains_key("input") {
+                scene.add_element(Element::new_module(self.input_manager.clone()));
+            }
+            if !scene.mod_alias.contains_key("input manager") {
+                scene.add_element(Element::new_module(self.input_manager.module()));
+            }
+            if !scene.mod_alias.contains_key("window") {
+                scene.add_element(Element::new_module(self.window_manager.clone()));
+            }
+            if !scene.mod_alias.contains_key("app") {
+                scene.add_element(Element::new_module(self.app_manager.clone()));
+            }
+            if !scene.mod_alias.contains_key("post process") {
+                scene.add_element(Element::new_module(post_process.clone()));
+            }
+            scene.init_elements();
+        }
+    }
+    /// Time step updates use, the one of a recording being played back over fixed_timestep
+    pub fn timestep(&self) -> Option<f32> {
+        self.input_manager.playback_timestep().or(self.fixed_timestep)
+    }
+    /// One frame without drawing: polls gamepads, updates the current scene and ends the input frame
+    /// `td` is replaced by the time step of a recording being played back, so replays match the recorded session
+    pub fn step(&mut self, td: f32) {
+        let td = self.input_manager.playback_timestep().unwrap_or(td);
+        self.input_manager.poll_gamepads(self.gamepads.as_mut());
+        if let Some(scene) = self.scene_manager.current_scene() {
+            scene.update_elements(td);
+        }
+        self.input_manager.next_frame(td);
+    }
     pub fn start(mut self) {
         use glium::{glutin, Surface};
+        #[allow(deprecated)]
+        let fullscreen = self.fullscreen;
+        if fullscreen && self.window.mode == crate::window::WindowMode::Windowed {
+            self.window.mode = crate::window::WindowMode::Borderless;
+        }
         let event_loop = glutin::event_loop::EventLoop::new();
-        let wb = glutin::window::WindowBuilder::new().with_inner_size(PhysicalSize { width: 800., height: 800. / self.aspect_ratio }).with_title("burge");
+        let size = self.window.size.unwrap_or([800, (800. / self.aspect_ratio) as u32]);
+        let wb = self.window.builder(size, &event_loop.primary_monitor());
 
 
-        let cb = glutin::ContextBuilder::new().with_vsync(true).with_srgb(true);
+        let cb = glutin::ContextBuilder::new().with_vsync(self.window.vsync).with_srgb(true);
         let display = glium::Display::new(wb, cb,&event_loop).unwrap();
+        {
+            let window = display.gl_window();
+            window.window().set_cursor_visible(self.window.cursor_visible);
+        }
+        // Grabbing can fail, so it goes through the window module, which only keeps it in the config once it worked
+        let grab = std::mem::replace(&mut self.window.cursor_grab, false);
+        self.window_manager.set_config(self.window.clone());
+        if grab {
+            self.window_manager.request(crate::window::WindowRequest::SetCursorGrab(true));
+            self.window_manager.apply(display.gl_window().window());
+        }
 
         
 
@@ -95,43 +162,17 @@ impl Instance {
 
 
         
-        let vertex_shader = include_str!("shaders/vertex.glsl");
-        let fragment_shader= include_str!("shaders/fragment.glsl");
-        let program = glium::Program::new(
-            &display,
-            glium::program::ProgramCreationInput::SourceCode {
-                vertex_shader,
-                tessellation_control_shader: None,
-                tessellation_evaluation_shader: None,
-                geometry_shader: None,
-                fragment_shader,
-                transform_feedback_varyings: None,
-                outputs_srgb: true,
-                uses_point_size: false,
-            },
-        ).unwrap();
-
-
-        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
-        use glium::uniforms::*;
-        let sampler_behavior = glium::uniforms::SamplerBehavior {
-            magnify_filter: MagnifySamplerFilter::Nearest,
-            minify_filter: MinifySamplerFilter::Nearest,
-            ..Default::default()
-        };
-        let draw_parameters = glium::DrawParameters {
-            depth: glium::Depth {
-                test: glium::draw_parameters::DepthTest::IfLess,
-                write: true,
-                .. Default::default()
-            },
-            blend: glium::draw_parameters::Blend::alpha_blending(),
-            .. Default::default()
-        };
-        let mut window_size: PhysicalSize<u32> = PhysicalSize {width: 800, height: (800. / self.aspect_ratio) as u32};
+        let mut renderer = crate::render::Renderer::new(&display);
+        if let Err((pass, e)) = self.post_process.compile(&display) {
+            panic!("Failed to compile post-processing pass {}: {}", pass, e);
+        }
+        let mut window_size: PhysicalSize<u32> = display.gl_window().window().inner_size();
 
 
-        let image = image::open(self.ss_path).unwrap().to_rgba8();
+        if self.sprite_sheet.image.is_none() {
+            self.sprite_sheet.load_image(self.ss_path).unwrap();
+        }
+        let image = self.sprite_sheet.image.clone().unwrap();
         let image_dimensions = image.dimensions();
         
         let image = glium::texture::RawImage2d::from_raw_rgba_reversed(&image.into_raw(), image_dimensions);
@@ -147,37 +188,71 @@ impl Instance {
             Some(texture)
         };*/
 
-        for (_, scene) in self.scene_manager.scenes.iter_mut() {
-            scene.init_elements();
-        }
+        self.init_scenes();
 
-        //let static_buffer = glium::VertexBuffer::new(&display, &self.scene_manager.current_scene().static_sprites).unwrap();
 
-
-        let mut time_delta: f32 = 0.0;
-        //std::thread::sleep(Duration::new(5,100_000));
+        let mut last_frame = Instant::now();
+        let mut next_frame = last_frame;
+        let mut accumulator: f32 = 0.0;
         let _ = event_loop.run(move |event, _, control_flow | {
-            std::thread::sleep(Duration::new(0,100_000));
-            
-            let start_time = Instant::now();
-            *control_flow = ControlFlow::Poll;
-            //*control_flow = ControlFlow::WaitUntil(Instant::now()+Duration::from_millis((unsafe { 1.0 / crate::FRAME_RATE }*1000.0 - time_delta*1000.0) as u64));
             match event {
                 glutin::event::Event::WindowEvent { event, .. } => match event {
-                    glutin::event::WindowEvent::CloseRequested => {
-                        //self.scene_controller.save();
-                        *control_flow = ControlFlow::Exit;
-                    },
+                    // Closed after the next update, when no element cancels it
+                    glutin::event::WindowEvent::CloseRequested => self.app_manager.send(AppEvent::CloseRequested),
+                    glutin::event::WindowEvent::Focused(true) => self.app_manager.send(AppEvent::FocusGained),
+                    glutin::event::WindowEvent::Focused(false) => self.app_manager.send(AppEvent::FocusLost),
                     glutin::event::WindowEvent::Resized(size) => {
+                        let minimized = size.width == 0 || size.height == 0;
+                        if minimized != (window_size.width == 0 || window_size.height == 0) {
+                            self.app_manager.send(if minimized { AppEvent::Minimized } else { AppEvent::Restored });
+                        }
+                        if !minimized {
+                            self.app_manager.send(AppEvent::Resized([size.width, size.height]));
+                        }
                         window_size = size;
+                        self.window_manager.set_size([size.width, size.height]);
                     },
-                    glutin::event::WindowEvent::KeyboardInput { input: KeyboardInput {scancode, state, ..}, .. } => {
+                    glutin::event::WindowEvent::KeyboardInput { input: KeyboardInput {scancode, state, virtual_keycode, ..}, .. } => {
                         
                         match state {
-                            ElementState::Pressed => self.input.send(InputEvent::KeyDown(scancode)),
-                            ElementState::Released => self.input.send(InputEvent::KeyUp(scancode)),
+                            ElementState::Pressed => self.input_manager.dispatch(InputEvent::KeyDown(scancode)),
+                            ElementState::Released => self.input_manager.dispatch(InputEvent::KeyUp(scancode)),
+                        }
+                        if let Some(key) = virtual_keycode {
+                            match state {
+                                ElementState::Pressed => self.input_manager.dispatch(InputEvent::VirtualKeyDown(key)),
+                                ElementState::Released => self.input_manager.dispatch(InputEvent::VirtualKeyUp(key)),
+                            }
+                        }
+                    },
+                    glutin::event::WindowEvent::MouseInput { state, button, .. } => {
+                        let button = match button {
+                            glutin::event::MouseButton::Left => MouseButton::Left,
+                            glutin::event::MouseButton::Right => MouseButton::Right,
+                            glutin::event::MouseButton::Middle => MouseButton::Middle,
+                            glutin::event::MouseButton::Other(b) => MouseButton::Other(b.into())
+                        };
+                        match state {
+                            ElementState::Pressed => self.input_manager.dispatch(InputEvent::MouseDown(button)),
+                            ElementState::Released => self.input_manager.dispatch(InputEvent::MouseUp(button)),
                         }
                     },
+                    glutin::event::WindowEvent::CursorMoved { position, .. } => {
+                        let window: V2<f32> = [position.x as f32, position.y as f32].into();
+                        let world = self.scene_manager.current_scene().and_then(|scene| scene.screen.screen_to_world(window));
+                        self.input_manager.dispatch(InputEvent::CursorMoved { window, world });
+                    },
+                    glutin::event::WindowEvent::MouseWheel { delta, .. } => {
+                        // Pixel deltas from touchpads are converted to roughly one line per 20 pixels
+                        let delta = match delta {
+                            MouseScrollDelta::LineDelta(x, y) => [x, y],
+                            MouseScrollDelta::PixelDelta(p) => [p.x as f32 / 20.0, p.y as f32 / 20.0]
+                        };
+                        self.input_manager.dispatch(InputEvent::Scroll(delta.into()));
+                    },
+                    glutin::event::WindowEvent::ReceivedCharacter(c) => self.input_manager.dispatch(InputEvent::Character(c)),
+                    glutin::event::WindowEvent::CursorEntered { .. } => self.input_manager.dispatch(InputEvent::CursorEntered),
+                    glutin::event::WindowEvent::CursorLeft { .. } => self.input_manager.dispatch(InputEvent::CursorLeft),
                     _ => ()
                 },
                 
@@ -187,27 +262,29 @@ impl Instance {
 
                     target.clear_color_and_depth(self.bg_color, 1.0);
 
-                    
+                    self.post_process.poll_events();
 
                     if let Some(scene) = self.scene_manager.current_scene() {
 
-                        
-                        let vertices = scene.display(&self.sprite_sheet);
-
-                        let vertex_buffer: glium::VertexBuffer<Vertex> = glium::VertexBuffer::new(&display, &vertices).unwrap();
-
-                        
-                        
-                        let (ortho_mat, translation) = scene.camera_projection(window_size.into());
-                        let uniforms = glium::uniform! {
-                            ortho_mat: ortho_mat,
-                            translation: translation,
-                            tex: glium::uniforms::Sampler(self.sprite_sheet.tex.as_ref().unwrap(), sampler_behavior)
+                        let bg_color = self.bg_color;
+                        scene.screen.set_output(window_size.into(), self.pixel_perfect.as_ref().map(|p| p.output_rect(window_size.into())));
+                        let mut frame = Frame {
+                            display: &display,
+                            renderer: &mut renderer,
+                            sprite_sheet: &self.sprite_sheet,
+                            pixel_perfect: &mut self.pixel_perfect,
+                            bg_color,
+                            window_size: window_size.into()
                         };
-                
-                        target.draw(&vertex_buffer, &indices, &program, &uniforms, &draw_parameters).unwrap();
+                        if self.post_process.is_active() {
+                            self.post_process.render(&display, &mut target, window_size.into(), |framebuffer| {
+                                framebuffer.clear_color_and_depth(bg_color, 1.0);
+                                frame.draw(framebuffer, scene);
+                            });
+                        } else {
+                            frame.draw(&mut target, scene);
+                        }
                         
-                        //target.draw(&static_buffer, &indices, &program, &uniforms, &draw_parameters).unwrap();
                         if self.scene_manager.map_editor {
                             
                         }
@@ -216,20 +293,160 @@ impl Instance {
                     }
                     target.finish().unwrap();
                 },
+                glutin::event::Event::Suspended => self.app_manager.send(AppEvent::Suspended),
+                glutin::event::Event::Resumed => self.app_manager.send(AppEvent::Resumed),
+                // Every window event of this iteration was handled, update and draw once per frame
                 glutin::event::Event::MainEventsCleared => {
-                    
-                }
+                    let now = Instant::now();
+                    let frame_rate = if self.app_manager.is_focused() { self.frame_rate } else { self.unfocused_frame_rate.or(self.frame_rate) };
+                    if frame_rate.is_some() && now < next_frame {
+                        *control_flow = ControlFlow::WaitUntil(next_frame);
+                        return;
+                    }
+                    let frame_time = (now - last_frame).as_secs_f32();
+                    last_frame = now;
+                    self.app_manager.record_frame(frame_time);
+                    // Long stalls, like dragging the window, don't turn into one huge update
+                    let time_delta = frame_time.min(0.25);
+
+                    self.window_manager.apply(display.gl_window().window());
+                    let stepped = match self.timestep() {
+                        Some(step) => {
+                            accumulator += time_delta;
+                            let mut steps = 0;
+                            while accumulator >= step && steps < 5 {
+                                self.step(step);
+                                accumulator -= step;
+                                steps += 1;
+                            }
+                            // Time that couldn't be caught up with is dropped
+                            accumulator = accumulator.min(step);
+                            steps > 0
+                        },
+                        None => {
+                            self.step(time_delta);
+                            true
+                        }
+                    };
+                    // Elements have seen the close request once they were updated
+                    if stepped && self.app_manager.should_close() {
+                        *control_flow = ControlFlow::Exit;
+                        return;
+                    }
+                    display.gl_window().window().request_redraw();
+
+                    *control_flow = match frame_rate {
+                        Some(rate) => {
+                            // Frames stay on schedule, unless one ran later than a whole frame
+                            let period = Duration::from_secs_f32(1.0 / rate.max(1.0));
+                            next_frame += period;
+                            if next_frame < now {
+                                next_frame = now + period;
+                            }
+                            ControlFlow::WaitUntil(next_frame)
+                        },
+                        None => ControlFlow::Poll
+                    };
+                },
                 _ => ()
             }
-            if let Some(scene) = self.scene_manager.current_scene() {
-                scene.update_elements(time_delta);
+        });
+    }
+}
+
+
+// Everything a scene is drawn with besides the target
+struct Frame<'a> {
+    display: &'a glium::Display,
+    renderer: &'a mut crate::render::Renderer,
+    sprite_sheet: &'a sprite::SpriteSheet,
+    pixel_perfect: &'a mut Option<crate::render::PixelPerfect>,
+    bg_color: (f32,f32,f32,f32),
+    window_size: [u32;2]
+}
+
+impl Frame<'_> {
+    // Draws the scene directly or through the pixel perfect upscaler
+    fn draw<S: Surface>(&mut self, target: &mut S, scene: &mut crate::scene::Scene) {
+        match self.pixel_perfect {
+            Some(pixel_perfect) => {
+                let resolution = pixel_perfect.resolution;
+                let (renderer, display, sprite_sheet, bg_color) = (&mut *self.renderer, self.display, self.sprite_sheet, self.bg_color);
+                pixel_perfect.render(display, target, self.window_size, |framebuffer| {
+                    framebuffer.clear_color_and_depth(bg_color, 1.0);
+                    renderer.draw_scene(display, framebuffer, scene, sprite_sheet, resolution);
+                });
+            },
+            None => self.renderer.draw_scene(self.display, target, scene, self.sprite_sheet, self.window_size)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, rc::Rc};
+    use crate::element::{Element, ElementBehavior, ModuleTool};
+    use crate::input::{Input, VirtualKeyCode};
+
+    // Moves right while the right key is held, by the time step of each update
+    struct Mover {
+        pos: Rc<Cell<f32>>,
+        input: Option<InputManager>
+    }
+    impl ElementBehavior for Mover {
+        fn init(&mut self, _uuid: uuid::Uuid, mods: &ModuleTool) {
+            self.input = mods.input();
+        }
+        fn local_update(&mut self, td: f32) {
+            if self.input.as_ref().is_some_and(|i| i.is_down(Input::Key(VirtualKeyCode::Right))) {
+                self.pos.set(self.pos.get() + td);
             }
-            display.gl_window().window().request_redraw();
+        }
+    }
 
-            std::thread::sleep(Duration::new(0,500_000));
+    fn instance(fixed_timestep: Option<f32>) -> (Instance, Rc<Cell<f32>>) {
+        let pos = Rc::new(Cell::new(0.0));
+        let mut scene = crate::scene::Scene::new();
+        scene.add_element(Element::new_gen(Mover { pos: pos.clone(), input: None }));
+        let mut instance = Instance::new();
+        instance.fixed_timestep = fixed_timestep;
+        instance.scene_manager.scenes.insert("main".to_string(), scene);
+        instance.scene_manager.set_scene("main").unwrap();
+        instance.init_scenes();
+        (instance, pos)
+    }
 
-            let end_time = Instant::now();
-            time_delta = (end_time-start_time).as_secs_f32();
-        });
+    #[test]
+    fn replays_recording_with_its_timestep() {
+        let (mut recorded, recorded_pos) = instance(Some(0.02));
+        let input = recorded.input();
+        input.start_recording(recorded.fixed_timestep);
+        for frame in 0..30 {
+            match frame {
+                3 | 20 => input.dispatch(InputEvent::VirtualKeyDown(VirtualKeyCode::Right)),
+                12 | 26 => input.dispatch(InputEvent::VirtualKeyUp(VirtualKeyCode::Right)),
+                _ => ()
+            }
+            recorded.step(0.02);
+        }
+        let recording = input.stop_recording().unwrap();
+        assert_eq!(recording.timestep, Some(0.02));
+        assert!((recorded_pos.get() - 0.3).abs() < 1e-4);
+
+        // Replayed without a fixed time step and stepped with another delta
+        let (mut replayed, replayed_pos) = instance(None);
+        replayed.input().play(Recording::from_json(&recording.to_json()).unwrap());
+        assert_eq!(replayed.timestep(), Some(0.02));
+        let mut frames = 0;
+        while replayed.input().is_playing() {
+            replayed.step(0.5);
+            frames += 1;
+        }
+        assert_eq!(frames, recording.frames);
+        assert_eq!(replayed_pos.get(), recorded_pos.get());
+        assert_eq!(replayed.input().frame(), recording.frames);
+        assert_eq!(replayed.timestep(), None);
     }
-}
\ No newline at end of file
+}
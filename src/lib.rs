@@ -8,7 +8,15 @@ pub mod element;
 pub mod event;
 pub mod scene;
 pub mod instance;
+pub mod input;
+pub mod window;
+pub mod app;
 pub mod sprite;
+pub mod text;
+pub mod render;
+pub mod post_process;
+pub mod offscreen;
+pub mod tiled;
 pub mod core;
 
 #[derive(Copy,Clone,Debug,Deserialize,Serialize)]
@@ -93,8 +101,9 @@ impl<T: Clone + Copy + Default> std::default::Default for V2<T> {
 pub struct Vertex {
     pub pos: [f32;3],
     pub tex_coords: [f32;2],
+    pub color: [f32;4],
     rotation: f32
 }
 
 
-glium::implement_vertex!(Vertex, pos, tex_coords, rotation);
\ No newline at end of file
+glium::implement_vertex!(Vertex, pos, tex_coords, color, rotation);
\ No newline at end of file
@@ -0,0 +1,309 @@
+use image::{Rgba, RgbaImage};
+
+use crate::*;
+
+
+/// Renders scenes on the CPU into an RGBA image, so visuals can be checked without a GPU or a window
+/// Follows the same layer, depth and blending rules as render::Renderer, post-processing is not applied
+pub struct OffscreenTarget {
+    pub size: [u32;2],
+    pub bg_color: (f32,f32,f32,f32),
+    color: RgbaImage,
+    depth: Vec<f32>
+}
+
+impl OffscreenTarget {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            size: [width, height],
+            bg_color: (0.0,0.0,0.0,1.0),
+            color: RgbaImage::new(width, height),
+            depth: vec![1.0; (width*height) as usize]
+        }
+    }
+    pub fn with_bg_color(mut self, bg_color: (f32,f32,f32,f32)) -> Self {
+        self.bg_color = bg_color;
+        self
+    }
+    pub fn image(&self) -> &RgbaImage {
+        &self.color
+    }
+    pub fn save(&self, path: &str) -> Result<(),String> {
+        self.color.save(path).map_err(|e| format!("Could not save {}: {}", path, e))
+    }
+
+    // The sprite sheet must have its image loaded with SpriteSheet::load_image
+    pub fn render_scene(&mut self, scene: &mut scene::Scene, sprite_sheet: &sprite::SpriteSheet) -> Result<&RgbaImage,String> {
+        let sheet = sprite_sheet.image.as_ref().ok_or("Sprite sheet image is not loaded".to_string())?;
+
+        let bg = Rgba([to_u8(self.bg_color.0), to_u8(self.bg_color.1), to_u8(self.bg_color.2), to_u8(self.bg_color.3)]);
+        for p in self.color.pixels_mut() {
+            *p = bg;
+        }
+
+        let layer_count = scene.layers().len();
+        scene.update_screen(self.size);
+        scene.static_display(sprite_sheet);
+        let scene: &scene::Scene = scene;
+        let geometry = scene.static_geometry();
+        let mut items = scene.display(sprite_sheet);
+        items.extend(geometry.translucent.iter().cloned());
+        let draw_list = render::DrawList::new(items, layer_count);
+
+        for (camera, viewport) in scene.views() {
+            let [left, bottom, width, height] = viewport.pixel_rect(self.size);
+            if width == 0 || height == 0 {
+                continue;
+            }
+            // Image rows start at the top
+            let rect = [left, self.size[1] - bottom - height, width, height];
+            if let Some(c) = viewport.clear_color {
+                let color = Rgba([to_u8(c[0]), to_u8(c[1]), to_u8(c[2]), to_u8(c[3])]);
+                for y in rect[1]..rect[1]+height {
+                    for x in left..left+width {
+                        self.color.put_pixel(x, y, color);
+                    }
+                }
+            }
+
+            for (idx, layer) in scene.layers().iter().enumerate() {
+                if !viewport.shows(layer) {
+                    continue;
+                }
+                // Depth only orders sprites within a layer
+                self.depth.iter_mut().for_each(|d| *d = 1.0);
+
+                let (ortho_mat, translation) = scene.layer_projection(layer, camera, [width, height]);
+                let ranges = &draw_list.layers[idx];
+
+                self.draw(&geometry.opaque[idx], sheet, ortho_mat, translation, rect, true);
+                self.draw(&draw_list.vertices[ranges.opaque.clone()], sheet, ortho_mat, translation, rect, true);
+                self.draw(&draw_list.vertices[ranges.translucent.clone()], sheet, ortho_mat, translation, rect, false);
+            }
+        }
+
+        Ok(&self.color)
+    }
+
+    // `rect` is the viewport's left, top, width and height in image pixels
+    fn draw(&mut self, vertices: &[Vertex], sheet: &RgbaImage, ortho_mat: [[f32;3];3], translation: [f32;2], rect: [u32;4], depth_write: bool) {
+        for tri in vertices.chunks_exact(3) {
+            let screen: Vec<([f32;3], [f32;2], [f32;4])> = tri.iter().map(|v| (to_screen(v, ortho_mat, translation, rect), v.tex_coords, v.color)).collect();
+            self.rasterize(&screen, sheet, rect, depth_write);
+        }
+    }
+
+    fn rasterize(&mut self, tri: &[([f32;3], [f32;2], [f32;4])], sheet: &RgbaImage, rect: [u32;4], depth_write: bool) {
+        let (a, b, c) = (tri[0].0, tri[1].0, tri[2].0);
+        let area = edge(a, b, c);
+        if area == 0.0 {
+            return;
+        }
+
+        let (rect_right, rect_bottom) = ((rect[0] + rect[2]) as i64, (rect[1] + rect[3]) as i64);
+        let min_x = (a[0].min(b[0]).min(c[0]).floor() as i64).clamp(rect[0] as i64, rect_right) as u32;
+        let min_y = (a[1].min(b[1]).min(c[1]).floor() as i64).clamp(rect[1] as i64, rect_bottom) as u32;
+        let max_x = (a[0].max(b[0]).max(c[0]).ceil() as i64).clamp(rect[0] as i64, rect_right) as u32;
+        let max_y = (a[1].max(b[1]).max(c[1]).ceil() as i64).clamp(rect[1] as i64, rect_bottom) as u32;
+
+        for py in min_y..max_y {
+            for px in min_x..max_x {
+                let p = [px as f32 + 0.5, py as f32 + 0.5, 0.0];
+                let w0 = edge(b, c, p) / area;
+                let w1 = edge(c, a, p) / area;
+                let w2 = edge(a, b, p) / area;
+                if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                    continue;
+                }
+
+                let z = w0*a[2] + w1*b[2] + w2*c[2];
+                // Outside of the clip volume
+                if !(-1.0..=1.0).contains(&z) {
+                    continue;
+                }
+                let depth = (z + 1.0) * 0.5;
+                let idx = (py*self.size[0] + px) as usize;
+                if depth > self.depth[idx] {
+                    continue;
+                }
+
+                let u = w0*tri[0].1[0] + w1*tri[1].1[0] + w2*tri[2].1[0];
+                let v = w0*tri[0].1[1] + w1*tri[1].1[1] + w2*tri[2].1[1];
+                let mut src = sample(sheet, u, v);
+                for i in 0..4 {
+                    let tint = w0*tri[0].2[i] + w1*tri[1].2[i] + w2*tri[2].2[i];
+                    src[i] = to_u8(src[i] as f32 / 255.0 * tint);
+                }
+                if src[3] == 0 {
+                    continue;
+                }
+
+                if depth_write {
+                    self.depth[idx] = depth;
+                }
+                let dst = self.color.get_pixel_mut(px, py);
+                *dst = blend(src, *dst);
+            }
+        }
+    }
+}
+
+
+// Same transform as shaders/vertex.glsl, followed by the viewport transform
+fn to_screen(v: &Vertex, m: [[f32;3];3], translation: [f32;2], rect: [u32;4]) -> [f32;3] {
+    let p = [v.pos[0] - translation[0], v.pos[1] - translation[1], v.pos[2]];
+    // Matrices are column major, as in GLSL
+    let x = m[0][0]*p[0] + m[1][0]*p[1] + m[2][0]*p[2];
+    let y = m[0][1]*p[0] + m[1][1]*p[1] + m[2][1]*p[2];
+    [
+        rect[0] as f32 + (x + 1.0) * 0.5 * rect[2] as f32,
+        rect[1] as f32 + (1.0 - (y + 1.0) * 0.5) * rect[3] as f32,
+        v.pos[2]
+    ]
+}
+
+fn edge(a: [f32;3], b: [f32;3], p: [f32;3]) -> f32 {
+    (b[0]-a[0])*(p[1]-a[1]) - (b[1]-a[1])*(p[0]-a[0])
+}
+
+fn to_u8(c: f32) -> u8 {
+    (c.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+// Nearest sampling, texture coordinates start at the bottom of the image like the uploaded texture
+fn sample(sheet: &RgbaImage, u: f32, v: f32) -> Rgba<u8> {
+    let (w, h) = sheet.dimensions();
+    let x = ((u * w as f32).floor() as i64).clamp(0, w as i64 - 1) as u32;
+    let y = ((v * h as f32).floor() as i64).clamp(0, h as i64 - 1) as u32;
+    *sheet.get_pixel(x, h - 1 - y)
+}
+
+// Matches Blend::alpha_blending
+fn blend(src: Rgba<u8>, dst: Rgba<u8>) -> Rgba<u8> {
+    let a = src[3] as f32 / 255.0;
+    let mut out = [0u8;4];
+    for i in 0..4 {
+        let s = src[i] as f32 / 255.0;
+        let d = dst[i] as f32 / 255.0;
+        out[i] = to_u8(s*a + d*(1.0 - a));
+    }
+    Rgba(out)
+}
+
+
+
+/// Differences between two images of the same size
+#[derive(Clone, Copy, Debug)]
+pub struct ImageDiff {
+    // Largest difference of any channel
+    pub max_difference: u8,
+    // Pixels with a channel differing by more than the tolerance
+    pub mismatched: usize
+}
+
+pub fn compare_images(a: &RgbaImage, b: &RgbaImage, tolerance: u8) -> Result<ImageDiff,String> {
+    if a.dimensions() != b.dimensions() {
+        return Err(format!("Image sizes differ: {:?} and {:?}", a.dimensions(), b.dimensions()))
+    }
+    let mut diff = ImageDiff { max_difference: 0, mismatched: 0 };
+    for (pa, pb) in a.pixels().zip(b.pixels()) {
+        let d = (0..4).map(|i| pa[i].abs_diff(pb[i])).max().unwrap();
+        diff.max_difference = diff.max_difference.max(d);
+        if d > tolerance {
+            diff.mismatched += 1;
+        }
+    }
+    Ok(diff)
+}
+
+/// Compares an image against the golden image at `path`, allowing `max_mismatched` pixels to differ by more than `tolerance`
+/// Golden images are only written when BURGE_UPDATE_GOLDEN is set, a missing one is an error otherwise
+/// On failure the rendered image is saved to the temp directory with an `.actual.png` extension
+pub fn check_golden(image: &RgbaImage, path: &str, tolerance: u8, max_mismatched: usize) -> Result<(),String> {
+    if std::env::var("BURGE_UPDATE_GOLDEN").is_ok() {
+        return image.save(path).map_err(|e| format!("Could not save {}: {}", path, e));
+    }
+    if !std::path::Path::new(path).exists() {
+        let actual = save_actual(image, path);
+        return Err(format!("Golden image {} is missing, rendered image saved to {}, set BURGE_UPDATE_GOLDEN to accept it", path, actual))
+    }
+    let golden = image::open(path).map_err(|e| format!("Could not open {}: {}", path, e))?.to_rgba8();
+    let diff = compare_images(image, &golden, tolerance)?;
+    if diff.mismatched > max_mismatched {
+        let actual = save_actual(image, path);
+        return Err(format!("{} pixels differ from {} (max difference {}), rendered image saved to {}", diff.mismatched, path, diff.max_difference, actual))
+    }
+    Ok(())
+}
+
+// Keeps rendered images out of the source tree, returns where the image was saved
+fn save_actual(image: &RgbaImage, path: &str) -> String {
+    let name = std::path::Path::new(path).file_stem().and_then(|s| s.to_str()).unwrap_or("golden");
+    let actual = std::env::temp_dir().join(format!("{}.actual.png", name));
+    let _ = image.save(&actual);
+    actual.display().to_string()
+}
+
+pub fn assert_golden(image: &RgbaImage, path: &str, tolerance: u8, max_mismatched: usize) {
+    if let Err(e) = check_golden(image, path, tolerance, max_mismatched) {
+        panic!("{}", e);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two by two sheet of solid red, green, blue and half transparent white tiles
+    fn sheet() -> sprite::SpriteSheet {
+        let mut sheet = sprite::SpriteSheet::new((2,2));
+        let mut image = RgbaImage::new(2, 2);
+        image.put_pixel(0, 1, Rgba([255,0,0,255]));
+        image.put_pixel(1, 1, Rgba([0,255,0,255]));
+        image.put_pixel(0, 0, Rgba([0,0,255,255]));
+        image.put_pixel(1, 0, Rgba([255,255,255,128]));
+        sheet.image = Some(image);
+        sheet
+    }
+
+    #[test]
+    fn renders_scene_like_golden() {
+        let mut scene = scene::Scene::new();
+        scene.add_element(element::Element::new_gen(core::Block::new_stretched([-0.5,-0.5].into(), [1.0,1.0].into(), 0)));
+        scene.add_element(element::Element::new_gen(core::Block::new_stretched([0.0,0.0].into(), [0.75,0.5].into(), 1).with_depth(-0.5)));
+        scene.add_element(element::Element::new_gen(core::Block::new_stretched([-0.25,-0.25].into(), [0.5,0.5].into(), 3).with_depth(-0.9)));
+        scene.init_elements();
+
+        let mut target = OffscreenTarget::new(32, 18).with_bg_color((0.1,0.1,0.1,1.0));
+        let image = target.render_scene(&mut scene, &sheet()).unwrap();
+        assert_golden(image, concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/blocks.png"), 2, 0);
+
+        // Worked out by hand, so the golden image isn't the only check of the rasterizer
+        // Background
+        assert_eq!(image.get_pixel(2, 2).0, [26,26,26,255]);
+        // Uncovered corner of the red block and the green block's center, a pixel is 1/16 of a world unit across and 1/9 up
+        assert_eq!(image.get_pixel(10, 12).0, [255,0,0,255]);
+        assert_eq!(image.get_pixel(22, 6).0, [0,255,0,255]);
+        // The green block is in front of the red one where they overlap
+        assert_eq!(image.get_pixel(17, 6).0, [0,255,0,255]);
+        // Half transparent white over red and over green, alpha 128/255 blended with the color below
+        assert_eq!(image.get_pixel(13, 9).0, [255,128,128,191]);
+        assert_eq!(image.get_pixel(18, 7).0, [128,255,128,191]);
+    }
+
+    #[test]
+    fn compares_within_tolerance() {
+        let a = RgbaImage::from_pixel(4, 4, Rgba([100,100,100,255]));
+        let mut b = a.clone();
+        b.put_pixel(0, 0, Rgba([103,100,100,255]));
+        b.put_pixel(1, 0, Rgba([100,90,100,255]));
+
+        let diff = compare_images(&a, &b, 3).unwrap();
+        assert_eq!(diff.max_difference, 10);
+        assert_eq!(diff.mismatched, 1);
+        assert_eq!(compare_images(&a, &b, 10).unwrap().mismatched, 0);
+        assert_eq!(compare_images(&a, &a, 0).unwrap().mismatched, 0);
+        assert!(compare_images(&a, &RgbaImage::new(4, 3), 0).is_err());
+    }
+}
@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Instant;
+use glium::Surface;
+
+use crate::*;
+
+
+#[derive(Clone, Copy)]
+struct PostVertex {
+    pos: [f32;2]
+}
+glium::implement_vertex!(PostVertex, pos);
+
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum UniformValue {
+    Bool(bool),
+    Float(f32),
+    Vec2([f32;2]),
+    Vec3([f32;3]),
+    Vec4([f32;4])
+}
+
+impl From<bool> for UniformValue {
+    fn from(value: bool) -> Self { UniformValue::Bool(value) }
+}
+impl From<f32> for UniformValue {
+    fn from(value: f32) -> Self { UniformValue::Float(value) }
+}
+impl From<[f32;2]> for UniformValue {
+    fn from(value: [f32;2]) -> Self { UniformValue::Vec2(value) }
+}
+impl From<[f32;3]> for UniformValue {
+    fn from(value: [f32;3]) -> Self { UniformValue::Vec3(value) }
+}
+impl From<[f32;4]> for UniformValue {
+    fn from(value: [f32;4]) -> Self { UniformValue::Vec4(value) }
+}
+
+impl UniformValue {
+    fn as_glium(&self) -> glium::uniforms::UniformValue<'static> {
+        use glium::uniforms::UniformValue as U;
+        match *self {
+            UniformValue::Bool(b) => U::Bool(b),
+            UniformValue::Float(f) => U::Float(f),
+            UniformValue::Vec2(v) => U::Vec2(v),
+            UniformValue::Vec3(v) => U::Vec3(v),
+            UniformValue::Vec4(v) => U::Vec4(v)
+        }
+    }
+}
+
+
+/// A fragment shader run over the whole screen
+/// Shaders receive the previous result as `scene`, along with `resolution` and `time`
+pub struct Pass {
+    pub name: String,
+    pub fragment_shader: String,
+    pub uniforms: HashMap<String, UniformValue>,
+    pub enabled: bool,
+
+    program: Option<glium::Program>
+}
+
+impl Pass {
+    pub fn new(name: &str, fragment_shader: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            fragment_shader: fragment_shader.to_string(),
+            uniforms: HashMap::new(),
+            enabled: true,
+
+            program: None
+        }
+    }
+    pub fn with_uniform(mut self, name: &str, value: impl Into<UniformValue>) -> Self {
+        self.uniforms.insert(name.to_string(), value.into());
+        self
+    }
+    // Builds the shader program, once
+    pub fn compile(&mut self, display: &glium::Display) -> Result<(), glium::ProgramCreationError> {
+        if self.program.is_none() {
+            self.program = Some(glium::Program::new(display, glium::program::ProgramCreationInput::SourceCode {
+                vertex_shader: include_str!("shaders/post/vertex.glsl"),
+                tessellation_control_shader: None,
+                tessellation_evaluation_shader: None,
+                geometry_shader: None,
+                fragment_shader: &self.fragment_shader,
+                transform_feedback_varyings: None,
+                outputs_srgb: true,
+                uses_point_size: false,
+            })?);
+        }
+        Ok(())
+    }
+
+    pub fn vignette() -> Self {
+        Self::new("vignette", include_str!("shaders/post/vignette.glsl"))
+            .with_uniform("strength", 0.6)
+            .with_uniform("radius", 0.45)
+    }
+    pub fn crt() -> Self {
+        Self::new("crt", include_str!("shaders/post/crt.glsl"))
+            .with_uniform("curvature", 0.05)
+            .with_uniform("scanlines", 0.25)
+    }
+    pub fn bloom() -> Self {
+        Self::new("bloom", include_str!("shaders/post/bloom.glsl"))
+            .with_uniform("threshold", 0.7)
+            .with_uniform("intensity", 1.0)
+            .with_uniform("radius", 2.0)
+    }
+    pub fn color_grading() -> Self {
+        Self::new("color grading", include_str!("shaders/post/color_grading.glsl"))
+            .with_uniform("brightness", 0.0)
+            .with_uniform("contrast", 1.0)
+            .with_uniform("saturation", 1.0)
+            .with_uniform("tint", [1.0,1.0,1.0])
+    }
+}
+
+
+struct PassUniforms<'a> {
+    scene: glium::uniforms::Sampler<'a, glium::Texture2d>,
+    resolution: [f32;2],
+    time: f32,
+    values: &'a HashMap<String, UniformValue>
+}
+
+impl<'b> glium::uniforms::Uniforms for PassUniforms<'b> {
+    fn visit_values<'a, F: FnMut(&str, glium::uniforms::UniformValue<'a>)>(&'a self, mut output: F) {
+        use glium::uniforms::AsUniformValue;
+        output("scene", self.scene.as_uniform_value());
+        output("resolution", glium::uniforms::UniformValue::Vec2(self.resolution));
+        output("time", glium::uniforms::UniformValue::Float(self.time));
+        for (name, value) in self.values {
+            output(name, value.as_glium());
+        }
+    }
+}
+
+
+#[derive(Clone)]
+pub enum PostProcessEvent {
+    SetUniform(String, String, UniformValue),
+    SetEnabled(String, bool)
+}
+
+
+/// Chain of post-processing passes applied to the rendered scene
+pub struct PostProcess {
+    passes: Vec<Pass>,
+
+    sender: event::Sender<PostProcessEvent>,
+    receiver: Rc<event::Receiver<PostProcessEvent>>,
+
+    start_time: Instant,
+    // Offscreen scene target and the two textures passes alternate between
+    targets: Option<([u32;2], glium::Texture2d, glium::framebuffer::DepthRenderBuffer, [glium::Texture2d;2])>,
+    quad: Option<glium::VertexBuffer<PostVertex>>
+}
+
+impl std::default::Default for PostProcess {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PostProcess {
+    pub fn new() -> Self {
+        let s = event::Sender::new();
+        Self {
+            passes: Vec::new(),
+
+            receiver: s.new_receiver(),
+            sender: s,
+
+            start_time: Instant::now(),
+            targets: None,
+            quad: None
+        }
+    }
+    // Passes are compiled by `compile`, which Instance::start calls once the display exists
+    pub fn add_pass(&mut self, pass: Pass) {
+        self.passes.push(pass);
+    }
+    // Compiles every pass, failing with the name of the first pass whose shader doesn't compile
+    pub fn compile(&mut self, display: &glium::Display) -> Result<(), (String, glium::ProgramCreationError)> {
+        for pass in self.passes.iter_mut() {
+            pass.compile(display).map_err(|e| (pass.name.clone(), e))?;
+        }
+        Ok(())
+    }
+    pub fn remove_pass(&mut self, name: &str) {
+        self.passes.retain(|p| p.name != name);
+    }
+    pub fn pass_mut(&mut self, name: &str) -> Option<&mut Pass> {
+        self.passes.iter_mut().find(|p| p.name == name)
+    }
+    pub fn set_uniform(&mut self, pass: &str, name: &str, value: impl Into<UniformValue>) {
+        if let Some(p) = self.pass_mut(pass) {
+            p.uniforms.insert(name.to_string(), value.into());
+        }
+    }
+    pub fn set_enabled(&mut self, pass: &str, enabled: bool) {
+        if let Some(p) = self.pass_mut(pass) {
+            p.enabled = enabled;
+        }
+    }
+    // Module for scenes, lets elements send PostProcessEvents
+    pub fn manager(&self) -> PostProcessManager {
+        PostProcessManager {
+            sender: self.sender.clone()
+        }
+    }
+    pub fn is_active(&self) -> bool {
+        self.passes.iter().any(|p| p.enabled)
+    }
+    pub fn poll_events(&mut self) {
+        for e in self.receiver.poll() {
+            match e {
+                PostProcessEvent::SetUniform(pass, name, value) => self.set_uniform(&pass, &name, value),
+                PostProcessEvent::SetEnabled(pass, enabled) => self.set_enabled(&pass, enabled)
+            }
+        }
+    }
+
+    // Renders the scene offscreen with `draw`, then runs every enabled pass, the last one into `target`
+    // Passes that weren't compiled are skipped
+    pub fn render<S: Surface, F: FnOnce(&mut glium::framebuffer::SimpleFrameBuffer)>(&mut self, display: &glium::Display, target: &mut S, size: [u32;2], draw: F) {
+        use glium::uniforms::*;
+
+        let size = [size[0].max(1), size[1].max(1)];
+        if self.targets.as_ref().map(|t| t.0) != Some(size) {
+            let texture = || glium::Texture2d::empty_with_format(display, glium::texture::UncompressedFloatFormat::U8U8U8U8, glium::texture::MipmapsOption::NoMipmap, size[0], size[1]).unwrap();
+            let depth = glium::framebuffer::DepthRenderBuffer::new(display, glium::texture::DepthFormat::I24, size[0], size[1]).unwrap();
+            self.targets = Some((size, texture(), depth, [texture(), texture()]));
+        }
+        if self.quad.is_none() {
+            self.quad = Some(glium::VertexBuffer::new(display, &[
+                PostVertex { pos: [-1.0,-1.0] }, PostVertex { pos: [1.0,-1.0] }, PostVertex { pos: [1.0,1.0] },
+                PostVertex { pos: [-1.0,-1.0] }, PostVertex { pos: [1.0,1.0] }, PostVertex { pos: [-1.0,1.0] },
+            ]).unwrap());
+        }
+
+        let (_, scene_tex, depth, swap) = self.targets.as_ref().unwrap();
+        {
+            let mut framebuffer = glium::framebuffer::SimpleFrameBuffer::with_depth_buffer(display, scene_tex, depth).unwrap();
+            draw(&mut framebuffer);
+        }
+
+        let sampler_behavior = SamplerBehavior {
+            magnify_filter: MagnifySamplerFilter::Nearest,
+            minify_filter: MinifySamplerFilter::Nearest,
+            wrap_function: (SamplerWrapFunction::Clamp, SamplerWrapFunction::Clamp, SamplerWrapFunction::Clamp),
+            ..Default::default()
+        };
+        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+        let quad = self.quad.as_ref().unwrap();
+        let time = self.start_time.elapsed().as_secs_f32();
+
+        let enabled: Vec<&Pass> = self.passes.iter().filter(|p| p.enabled && p.program.is_some()).collect();
+        if enabled.is_empty() {
+            scene_tex.as_surface().fill(target, MagnifySamplerFilter::Nearest);
+            return
+        }
+        let mut source = scene_tex;
+        for (idx, pass) in enabled.iter().enumerate() {
+            let uniforms = PassUniforms {
+                scene: Sampler(source, sampler_behavior),
+                resolution: [size[0] as f32, size[1] as f32],
+                time,
+                values: &pass.uniforms
+            };
+            let program = pass.program.as_ref().unwrap();
+            if idx == enabled.len() - 1 {
+                target.draw(quad, indices, program, &uniforms, &Default::default()).unwrap();
+            } else {
+                let destination = &swap[idx % 2];
+                let mut framebuffer = glium::framebuffer::SimpleFrameBuffer::new(display, destination).unwrap();
+                framebuffer.draw(quad, indices, program, &uniforms, &Default::default()).unwrap();
+                source = destination;
+            }
+        }
+    }
+}
+
+
+#[derive(Clone)]
+pub struct PostProcessManager {
+    sender: event::Sender<PostProcessEvent>
+}
+
+impl element::ModuleBehavior for PostProcessManager {
+    fn alias(&self) -> String {
+        "post process".to_string()
+    }
+    fn component(&self) -> &dyn std::any::Any {
+        &self.sender
+    }
+}
+impl element::ElementBehavior for PostProcessManager {
+    fn load(&self, _data: &serde_json::Map<String,serde_json::Value>) -> element::Element {
+        element::Element::new_module(self.clone())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post_process() -> PostProcess {
+        let mut post_process = PostProcess::new();
+        post_process.add_pass(Pass::vignette());
+        post_process.add_pass(Pass::crt());
+        post_process
+    }
+
+    // Sends an event the way elements do, through the scene's "post process" module
+    fn send(manager: &PostProcessManager, event: PostProcessEvent) {
+        let mut scene = scene::Scene::new();
+        scene.add_element(element::Element::new_module(manager.clone()));
+        let mut sent = false;
+        scene.module_tool().access("post process", |sender: &event::Sender<PostProcessEvent>| {
+            sender.send(event.clone());
+            sent = true;
+        });
+        assert!(sent);
+    }
+
+    fn strength(post_process: &mut PostProcess) -> Option<f32> {
+        match post_process.pass_mut("vignette")?.uniforms.get("strength") {
+            Some(UniformValue::Float(f)) => Some(*f),
+            _ => None
+        }
+    }
+
+    #[test]
+    fn sets_uniforms_from_events() {
+        let mut post_process = post_process();
+        let manager = post_process.manager();
+
+        send(&manager, PostProcessEvent::SetUniform("vignette".to_string(), "strength".to_string(), 0.2.into()));
+        // Events wait for the next poll
+        assert_eq!(strength(&mut post_process), Some(0.6));
+        post_process.poll_events();
+        assert_eq!(strength(&mut post_process), Some(0.2));
+
+        // Unknown passes are ignored
+        send(&manager, PostProcessEvent::SetUniform("blur".to_string(), "strength".to_string(), 1.0.into()));
+        post_process.poll_events();
+        assert_eq!(strength(&mut post_process), Some(0.2));
+        assert!(post_process.pass_mut("blur").is_none());
+    }
+
+    #[test]
+    fn enables_passes_from_events() {
+        let mut post_process = post_process();
+        let manager = post_process.manager();
+        assert!(post_process.is_active());
+
+        for pass in ["vignette", "crt"] {
+            send(&manager, PostProcessEvent::SetEnabled(pass.to_string(), false));
+        }
+        post_process.poll_events();
+        assert!(!post_process.is_active());
+
+        send(&manager, PostProcessEvent::SetEnabled("crt".to_string(), true));
+        post_process.poll_events();
+        assert!(post_process.pass_mut("crt").unwrap().enabled);
+        assert!(!post_process.pass_mut("vignette").unwrap().enabled);
+    }
+}
@@ -0,0 +1,519 @@
+use std::cmp::Ordering;
+use std::ops::Range;
+use glium::Surface;
+
+use crate::*;
+
+
+pub const WORLD_LAYER: &str = "world";
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderLayer {
+    pub name: String,
+    // Multiplies the camera offset, layers below 1.0 scroll slower than the world
+    pub parallax: V2<f32>,
+    // Drawn in window pixels from the bottom left instead of through the camera
+    pub screen_space: bool
+}
+
+impl std::default::Default for RenderLayer {
+    fn default() -> Self {
+        Self {
+            name: WORLD_LAYER.to_string(),
+            parallax: [1.0,1.0].into(),
+            screen_space: false
+        }
+    }
+}
+
+impl RenderLayer {
+    pub fn new(name: &str, parallax: V2<f32>) -> Self {
+        Self {
+            name: name.to_string(),
+            parallax,
+            screen_space: false
+        }
+    }
+    pub fn screen(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            parallax: [0.0,0.0].into(),
+            screen_space: true
+        }
+    }
+}
+
+// Layers are drawn in order, so later layers always cover earlier ones
+pub fn default_layers() -> Vec<RenderLayer> {
+    vec![
+        RenderLayer::new("background", [0.5,0.5].into()),
+        RenderLayer::new(WORLD_LAYER, [1.0,1.0].into()),
+        RenderLayer::new("foreground", [1.0,1.0].into()),
+        RenderLayer::screen("ui")
+    ]
+}
+
+/// Part of the window a camera draws to, and what it draws
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Viewport {
+    // Left, bottom, width and height as fractions of the window
+    pub rect: [f32;4],
+    // Cleared before drawing, the viewport is drawn over what is already there when unset
+    pub clear_color: Option<[f32;4]>,
+    // Names of the layers drawn, every layer when empty
+    pub layers: Vec<String>
+}
+
+impl std::default::Default for Viewport {
+    fn default() -> Self {
+        Self {
+            rect: [0.0,0.0,1.0,1.0],
+            clear_color: None,
+            layers: Vec::new()
+        }
+    }
+}
+
+impl Viewport {
+    pub fn new(rect: [f32;4]) -> Self {
+        Self {
+            rect,
+            ..Default::default()
+        }
+    }
+    pub fn with_clear_color(mut self, color: [f32;4]) -> Self {
+        self.clear_color = Some(color);
+        self
+    }
+    pub fn with_layers(mut self, layers: &[&str]) -> Self {
+        self.layers = layers.iter().map(|l| l.to_string()).collect();
+        self
+    }
+    pub fn shows(&self, layer: &RenderLayer) -> bool {
+        self.layers.is_empty() || self.layers.contains(&layer.name)
+    }
+    // Left, bottom, width and height in window pixels
+    pub fn pixel_rect(&self, window_size: [u32;2]) -> [u32;4] {
+        let (w, h) = (window_size[0] as f32, window_size[1] as f32);
+        let left = (self.rect[0]*w).round().clamp(0.0, w);
+        let bottom = (self.rect[1]*h).round().clamp(0.0, h);
+        let right = ((self.rect[0] + self.rect[2])*w).round().clamp(left, w);
+        let top = ((self.rect[1] + self.rect[3])*h).round().clamp(bottom, h);
+        [left as u32, bottom as u32, (right - left) as u32, (top - bottom) as u32]
+    }
+}
+
+
+// Maps window pixels, translated by half the window size, to clip space
+pub fn screen_projection(window_size: [u32;2]) -> [[f32;3];3] {
+    [[2.0/window_size[0].max(1) as f32, 0.0, 0.0],
+    [0.0, 2.0/window_size[1].max(1) as f32, 0.0],
+    [0.0, 0.0, 1.0f32]]
+}
+
+// Unknown or missing layer names fall back to the world layer
+pub fn layer_index(layers: &[RenderLayer], name: Option<&str>) -> usize {
+    let name = name.unwrap_or(WORLD_LAYER);
+    layers.iter().position(|l| l.name == name)
+        .or(layers.iter().position(|l| l.name == WORLD_LAYER))
+        .unwrap_or(0)
+}
+
+
+
+/// Vertices of a single sprite along with the keys used to order it
+#[derive(Clone)]
+pub struct DrawItem {
+    pub layer: usize,
+    pub depth: f32,
+    pub y: f32,
+    // Position of the owning element in the scene, breaks ties
+    pub order: usize,
+    pub translucent: bool,
+    pub vertices: Vec<Vertex>
+}
+
+impl DrawItem {
+    // Back to front: by layer, then from highest to lowest depth, then from highest to lowest y
+    pub fn draw_order(&self, other: &DrawItem) -> Ordering {
+        self.layer.cmp(&other.layer)
+            .then(other.depth.total_cmp(&self.depth))
+            .then(other.y.total_cmp(&self.y))
+            .then(self.order.cmp(&other.order))
+    }
+}
+
+/// World area in view on each layer, so elements can leave out what isn't drawn
+#[derive(Clone, Default)]
+pub struct VisibleArea {
+    // Bottom left and top right corner per layer, None on layers no camera shows
+    // Everything is visible when there are no layers
+    layers: Option<Vec<Option<[V2<f32>;2]>>>
+}
+
+impl VisibleArea {
+    // Everything is in view, used for geometry that's kept across frames
+    pub fn all() -> Self {
+        Self::default()
+    }
+    pub fn new(layer_count: usize) -> Self {
+        Self {
+            layers: Some(vec![None; layer_count])
+        }
+    }
+    // Grows a layer's area to cover a rectangle
+    pub fn add(&mut self, layer: usize, min: V2<f32>, max: V2<f32>) {
+        if let Some(area) = self.layers.as_mut().and_then(|l| l.get_mut(layer)) {
+            *area = Some(match *area {
+                Some([lo, hi]) => [V2 { x: lo.x.min(min.x), y: lo.y.min(min.y) }, V2 { x: hi.x.max(max.x), y: hi.y.max(max.y) }],
+                None => [min, max]
+            });
+        }
+    }
+    pub fn shows(&self, layer: usize, min: V2<f32>, max: V2<f32>) -> bool {
+        match &self.layers {
+            Some(layers) => match layers.get(layer) {
+                Some(Some([lo, hi])) => min.x <= hi.x && max.x >= lo.x && min.y <= hi.y && max.y >= lo.y,
+                Some(None) => false,
+                None => true
+            },
+            None => true
+        }
+    }
+}
+
+// Splits a sprite chain into one item per sprite
+pub fn sprite_items(sprite: sprite::Sprite, sprite_sheet: &sprite::SpriteSheet, layers: &[RenderLayer], order: usize, items: &mut Vec<DrawItem>) {
+    let mut current = &Some(Box::new(sprite));
+    while let Some(s) = current {
+        if !s.tex_indices.is_empty() || s.uv.is_some() {
+            items.push(DrawItem {
+                layer: layer_index(layers, s.layer.as_deref()),
+                depth: s.depth,
+                y: s.pos.y,
+                order,
+                translucent: s.translucent,
+                vertices: sprite_sheet.sprite_vertices(s)
+            });
+        }
+        current = &s.next;
+    }
+}
+
+
+
+/// Static geometry of a scene, opaque vertices are baked per layer
+/// while translucent items are kept so they can be sorted with dynamic ones
+#[derive(Default)]
+pub struct StaticGeometry {
+    pub opaque: Vec<Vec<Vertex>>,
+    pub translucent: Vec<DrawItem>
+}
+
+#[derive(Clone, Default)]
+pub struct LayerRanges {
+    pub opaque: Range<usize>,
+    pub translucent: Range<usize>
+}
+
+/// Sorted vertices for one frame, with the ranges belonging to each layer
+pub struct DrawList {
+    pub vertices: Vec<Vertex>,
+    pub layers: Vec<LayerRanges>
+}
+
+impl DrawList {
+    pub fn new(mut items: Vec<DrawItem>, layer_count: usize) -> Self {
+        items.sort_by(|a, b| a.draw_order(b));
+
+        let mut vertices = Vec::new();
+        let mut layers = vec![LayerRanges::default(); layer_count];
+        for (idx, ranges) in layers.iter_mut().enumerate() {
+            for translucent in [false, true] {
+                let start = vertices.len();
+                for item in items.iter().filter(|i| i.layer == idx && i.translucent == translucent) {
+                    vertices.extend_from_slice(&item.vertices);
+                }
+                if translucent {
+                    ranges.translucent = start..vertices.len();
+                } else {
+                    ranges.opaque = start..vertices.len();
+                }
+            }
+        }
+
+        Self {
+            vertices,
+            layers
+        }
+    }
+}
+
+
+
+// Opaque static geometry uploaded per layer, keyed by scene id and static generation
+struct StaticBuffers {
+    key: (Uuid, u64),
+    layers: Vec<Option<glium::VertexBuffer<Vertex>>>
+}
+
+/// Draws scenes with the sprite shader, keeping static geometry in persistent buffers
+pub struct Renderer {
+    program: glium::Program,
+    // Only uploaded again when the scene's static geometry changes
+    static_buffers: Option<StaticBuffers>,
+    dynamic_buffer: Option<glium::VertexBuffer<Vertex>>
+}
+
+impl Renderer {
+    pub fn new(display: &glium::Display) -> Self {
+        let vertex_shader = include_str!("shaders/vertex.glsl");
+        let fragment_shader= include_str!("shaders/fragment.glsl");
+        let program = glium::Program::new(
+            display,
+            glium::program::ProgramCreationInput::SourceCode {
+                vertex_shader,
+                tessellation_control_shader: None,
+                tessellation_evaluation_shader: None,
+                geometry_shader: None,
+                fragment_shader,
+                transform_feedback_varyings: None,
+                outputs_srgb: true,
+                uses_point_size: false,
+            },
+        ).unwrap();
+
+        Self {
+            program,
+            static_buffers: None,
+            dynamic_buffer: None
+        }
+    }
+
+    pub fn draw_scene<S: Surface>(&mut self, display: &glium::Display, target: &mut S, scene: &mut scene::Scene, sprite_sheet: &sprite::SpriteSheet, window_size: [u32;2]) {
+        use glium::uniforms::*;
+
+        let layer_count = scene.layers().len();
+        scene.update_screen(window_size);
+
+        scene.static_display(sprite_sheet);
+        let scene: &scene::Scene = scene;
+        let geometry = scene.static_geometry();
+        let static_key = (scene.id, scene.static_generation());
+        if self.static_buffers.as_ref().map(|b| b.key) != Some(static_key) {
+            let layers = geometry.opaque.iter().map(|vertices| {
+                if vertices.is_empty() {
+                    None
+                } else {
+                    Some(glium::VertexBuffer::new(display, vertices).unwrap())
+                }
+            }).collect();
+            self.static_buffers = Some(StaticBuffers { key: static_key, layers });
+        }
+
+        let mut items = scene.display(sprite_sheet);
+        items.extend(geometry.translucent.iter().cloned());
+        let draw_list = DrawList::new(items, layer_count);
+
+        if self.dynamic_buffer.as_ref().is_none_or(|b| b.len() < draw_list.vertices.len()) {
+            self.dynamic_buffer = Some(glium::VertexBuffer::empty_dynamic(display, draw_list.vertices.len().next_power_of_two()).unwrap());
+        }
+        let dynamic_buffer = self.dynamic_buffer.as_ref().unwrap();
+        if !draw_list.vertices.is_empty() {
+            dynamic_buffer.slice(0..draw_list.vertices.len()).unwrap().write(&draw_list.vertices);
+        }
+
+
+        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+        let sampler_behavior = SamplerBehavior {
+            magnify_filter: MagnifySamplerFilter::Nearest,
+            minify_filter: MinifySamplerFilter::Nearest,
+            ..Default::default()
+        };
+        let opaque_parameters = glium::DrawParameters {
+            depth: glium::Depth {
+                test: glium::draw_parameters::DepthTest::IfLessOrEqual,
+                write: true,
+                .. Default::default()
+            },
+            blend: glium::draw_parameters::Blend::alpha_blending(),
+            .. Default::default()
+        };
+        let translucent_parameters = glium::DrawParameters {
+            depth: glium::Depth {
+                test: glium::draw_parameters::DepthTest::IfLessOrEqual,
+                write: false,
+                .. Default::default()
+            },
+            .. opaque_parameters.clone()
+        };
+
+        let tex = sprite_sheet.tex.as_ref().unwrap();
+
+        for (camera, viewport) in scene.views() {
+            let [left, bottom, width, height] = viewport.pixel_rect(window_size);
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let rect = glium::Rect { left, bottom, width, height };
+            if let Some(c) = viewport.clear_color {
+                target.clear(Some(&rect), Some((c[0], c[1], c[2], c[3])), false, None, None);
+            }
+            let opaque_parameters = glium::DrawParameters {
+                viewport: Some(rect),
+                .. opaque_parameters.clone()
+            };
+            let translucent_parameters = glium::DrawParameters {
+                viewport: Some(rect),
+                .. translucent_parameters.clone()
+            };
+
+            for (idx, layer) in scene.layers().iter().enumerate() {
+                if !viewport.shows(layer) {
+                    continue;
+                }
+                // Depth only orders sprites within a layer
+                target.clear(Some(&rect), None, false, Some(1.0), None);
+
+                let (ortho_mat, translation) = scene.layer_projection(layer, camera, [width, height]);
+                let uniforms = glium::uniform! {
+                    ortho_mat: ortho_mat,
+                    translation: translation,
+                    tex: Sampler(tex, sampler_behavior)
+                };
+
+                if let Some(buffers) = &self.static_buffers {
+                    if let Some(Some(buffer)) = buffers.layers.get(idx) {
+                        target.draw(buffer, indices, &self.program, &uniforms, &opaque_parameters).unwrap();
+                    }
+                }
+                let ranges = &draw_list.layers[idx];
+                if !ranges.opaque.is_empty() {
+                    let slice = dynamic_buffer.slice(ranges.opaque.clone()).unwrap();
+                    target.draw(slice, indices, &self.program, &uniforms, &opaque_parameters).unwrap();
+                }
+                if !ranges.translucent.is_empty() {
+                    let slice = dynamic_buffer.slice(ranges.translucent.clone()).unwrap();
+                    target.draw(slice, indices, &self.program, &uniforms, &translucent_parameters).unwrap();
+                }
+            }
+        }
+    }
+}
+
+
+
+/// Renders at a fixed virtual resolution, then scales it up by the largest whole number that fits the window
+/// The rest of the window is filled with the letterbox color
+pub struct PixelPerfect {
+    pub resolution: [u32;2],
+    pub letterbox_color: (f32,f32,f32,f32),
+    target: Option<([u32;2], glium::Texture2d, glium::framebuffer::DepthRenderBuffer)>
+}
+
+impl PixelPerfect {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            resolution: [width.max(1), height.max(1)],
+            letterbox_color: (0.0,0.0,0.0,1.0),
+            target: None
+        }
+    }
+    pub fn with_letterbox_color(mut self, color: (f32,f32,f32,f32)) -> Self {
+        self.letterbox_color = color;
+        self
+    }
+
+    pub fn scale(&self, window_size: [u32;2]) -> u32 {
+        (window_size[0] / self.resolution[0]).min(window_size[1] / self.resolution[1]).max(1)
+    }
+    // Left, bottom, width and height of the upscaled image in window pixels, centered
+    pub fn output_rect(&self, window_size: [u32;2]) -> [u32;4] {
+        let scale = self.scale(window_size);
+        let (width, height) = (self.resolution[0]*scale, self.resolution[1]*scale);
+        [window_size[0].saturating_sub(width)/2, window_size[1].saturating_sub(height)/2, width, height]
+    }
+
+    // `draw` receives a framebuffer of the virtual resolution
+    pub fn render<S: Surface, F: FnOnce(&mut glium::framebuffer::SimpleFrameBuffer)>(&mut self, display: &glium::Display, target: &mut S, window_size: [u32;2], draw: F) {
+        let size = self.resolution;
+        if self.target.as_ref().map(|t| t.0) != Some(size) {
+            let texture = glium::Texture2d::empty_with_format(display, glium::texture::UncompressedFloatFormat::U8U8U8U8, glium::texture::MipmapsOption::NoMipmap, size[0], size[1]).unwrap();
+            let depth = glium::framebuffer::DepthRenderBuffer::new(display, glium::texture::DepthFormat::I24, size[0], size[1]).unwrap();
+            self.target = Some((size, texture, depth));
+        }
+        let (_, texture, depth) = self.target.as_ref().unwrap();
+        {
+            let mut framebuffer = glium::framebuffer::SimpleFrameBuffer::with_depth_buffer(display, texture, depth).unwrap();
+            draw(&mut framebuffer);
+        }
+
+        target.clear_color(self.letterbox_color.0, self.letterbox_color.1, self.letterbox_color.2, self.letterbox_color.3);
+        let [left, bottom, width, height] = self.output_rect(window_size);
+        texture.as_surface().blit_color(
+            &glium::Rect { left: 0, bottom: 0, width: size[0], height: size[1] },
+            target,
+            &glium::BlitTarget { left, bottom, width: width as i32, height: height as i32 },
+            glium::uniforms::MagnifySamplerFilter::Nearest
+        );
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One vertex tagged with `id` so the sorted output can be read back
+    fn item(id: usize, layer: usize, depth: f32, y: f32, order: usize, translucent: bool) -> DrawItem {
+        DrawItem {
+            layer,
+            depth,
+            y,
+            order,
+            translucent,
+            vertices: vec![Vertex { pos: [id as f32, 0.0, 0.0], tex_coords: [0.0, 0.0], color: [1.0; 4], rotation: 0.0 }]
+        }
+    }
+
+    fn ids(list: &DrawList, range: Range<usize>) -> Vec<usize> {
+        list.vertices[range].iter().map(|v| v.pos[0] as usize).collect()
+    }
+
+    #[test]
+    fn breaks_ties_by_element_order() {
+        let list = DrawList::new(vec![item(0, 0, 0.0, 1.0, 2, false), item(1, 0, 0.0, 1.0, 0, false), item(2, 0, 0.0, 1.0, 1, false)], 1);
+        assert_eq!(ids(&list, list.layers[0].opaque.clone()), vec![1, 2, 0]);
+        assert_eq!(item(0, 0, 0.0, 1.0, 3, false).draw_order(&item(1, 0, 0.0, 1.0, 3, false)), Ordering::Equal);
+    }
+
+    #[test]
+    fn sorts_by_depth_then_y_within_a_layer() {
+        let list = DrawList::new(vec![
+            item(0, 0, 0.0, -1.0, 0, false),
+            item(1, 0, 0.0, 2.0, 1, false),
+            item(2, 0, 0.5, -5.0, 2, false),
+            item(3, 0, 0.0, 0.5, 3, false)
+        ], 1);
+        // Deeper items first, then higher ones so lower items are drawn over them
+        assert_eq!(ids(&list, list.layers[0].opaque.clone()), vec![2, 1, 3, 0]);
+    }
+
+    #[test]
+    fn draws_translucent_items_after_opaque_ones() {
+        let list = DrawList::new(vec![
+            item(0, 1, 0.0, 0.0, 0, false),
+            item(1, 0, -1.0, 0.0, 1, true),
+            item(2, 0, 1.0, 0.0, 2, false),
+            item(3, 0, 0.0, 0.0, 3, false)
+        ], 2);
+        let layer = &list.layers[0];
+        assert_eq!(ids(&list, layer.opaque.clone()), vec![2, 3]);
+        assert_eq!(ids(&list, layer.translucent.clone()), vec![1]);
+        assert_eq!(layer.opaque.end, layer.translucent.start);
+        assert_eq!(ids(&list, list.layers[1].opaque.clone()), vec![0]);
+        assert!(list.layers[1].translucent.is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use std::{cell::RefCell, collections::HashMap, rc::Weak};
+use std::{cell::{Cell, RefCell}, collections::HashMap, rc::Weak};
 use crate::*;
 use std::rc::Rc;
 
@@ -36,6 +36,12 @@ impl UuidTags {
             all_tags.insert(*uuid, vec![tag]);
         }
     }
+    pub fn with_tag(&self, tag: &str) -> Vec<Uuid> {
+        self.tags.borrow().iter()
+            .filter(|(_, tags)| tags.iter().any(|t| t == tag))
+            .map(|(uuid, _)| *uuid)
+            .collect()
+    }
     pub fn has_tag(&self, uuid: &Uuid, tag: String) -> bool {
         if let Some(tags) = self.tags.borrow().get(uuid) {
             tags.contains(&tag)
@@ -58,21 +64,203 @@ impl element::ModuleBehavior for UuidTags {
 
 
 
+/// Positions reported by elements through ElementBehavior::position, updated after local_update
+#[derive(Clone,Default)]
+pub struct Positions {
+    positions: Rc<RefCell<HashMap<Uuid, V2<f32>>>>
+}
+
+impl Positions {
+    pub fn get(&self, uuid: &Uuid) -> Option<V2<f32>> {
+        self.positions.borrow().get(uuid).copied()
+    }
+    pub fn set(&self, uuid: &Uuid, pos: Option<V2<f32>>) {
+        let mut positions = self.positions.borrow_mut();
+        match pos {
+            Some(pos) => { positions.insert(*uuid, pos); },
+            None => { positions.remove(uuid); }
+        }
+    }
+}
+
+impl element::ElementBehavior for Positions {}
+impl element::ModuleBehavior for Positions {
+    fn alias(&self) -> String {
+        "positions".to_string()
+    }
+    fn component(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+
+/// How a camera mapped the world onto the render target when the scene was drawn
+#[derive(Clone,Copy,Debug)]
+pub struct ViewTransform {
+    // Left, bottom, width and height of the camera's viewport in render target pixels
+    pub rect: [u32;4],
+    pub clip_matrix: [[f32;3];3],
+    pub offset: [f32;2]
+}
+
+impl ViewTransform {
+    pub fn contains(&self, pos: [f32;2]) -> bool {
+        pos[0] >= self.rect[0] as f32 && pos[1] >= self.rect[1] as f32
+            && pos[0] < (self.rect[0] + self.rect[2]) as f32 && pos[1] < (self.rect[1] + self.rect[3]) as f32
+    }
+    // Render target pixels from the bottom left to world units, the inverse of the vertex shader
+    pub fn to_world(&self, pos: [f32;2]) -> V2<f32> {
+        let ndc = [
+            (pos[0] - self.rect[0] as f32) / self.rect[2].max(1) as f32 * 2.0 - 1.0,
+            (pos[1] - self.rect[1] as f32) / self.rect[3].max(1) as f32 * 2.0 - 1.0
+        ];
+        // Matrices are column major
+        let m = self.clip_matrix;
+        let det = m[0][0]*m[1][1] - m[1][0]*m[0][1];
+        if det == 0.0 {
+            return self.offset.into()
+        }
+        [
+            (m[1][1]*ndc[0] - m[1][0]*ndc[1]) / det + self.offset[0],
+            (m[0][0]*ndc[1] - m[0][1]*ndc[0]) / det + self.offset[1]
+        ].into()
+    }
+    pub fn to_target(&self, pos: V2<f32>) -> [f32;2] {
+        let p = [pos.x - self.offset[0], pos.y - self.offset[1]];
+        let m = self.clip_matrix;
+        let ndc = [m[0][0]*p[0] + m[1][0]*p[1], m[0][1]*p[0] + m[1][1]*p[1]];
+        [
+            self.rect[0] as f32 + (ndc[0] + 1.0) * 0.5 * self.rect[2] as f32,
+            self.rect[1] as f32 + (ndc[1] + 1.0) * 0.5 * self.rect[3] as f32
+        ]
+    }
+}
+
+
+/// Size of the render target the scene was last drawn to, and where it was shown in the window
+/// Converts between window pixels and world units using the cameras of the last drawn frame
+#[derive(Clone,Default)]
+pub struct Screen {
+    size: Rc<Cell<[u32;2]>>,
+    window_size: Rc<Cell<[u32;2]>>,
+    // Left, bottom, width and height of the render target in the window, the whole window when unset
+    output: Rc<Cell<Option<[u32;4]>>>,
+    views: Rc<RefCell<Vec<ViewTransform>>>
+}
+
+impl Screen {
+    pub fn size(&self) -> [u32;2] {
+        self.size.get()
+    }
+    pub fn set_size(&self, size: [u32;2]) {
+        self.size.set(size)
+    }
+    pub fn window_size(&self) -> [u32;2] {
+        let window_size = self.window_size.get();
+        if window_size[0] == 0 || window_size[1] == 0 { self.size.get() } else { window_size }
+    }
+    // Used when the render target is scaled into part of the window, as in pixel perfect mode
+    pub fn set_output(&self, window_size: [u32;2], rect: Option<[u32;4]>) {
+        self.window_size.set(window_size);
+        self.output.set(rect);
+    }
+    pub fn views(&self) -> Vec<ViewTransform> {
+        self.views.borrow().clone()
+    }
+    pub fn set_views(&self, views: Vec<ViewTransform>) {
+        *self.views.borrow_mut() = views;
+    }
+
+    // Window pixels from the top left, as reported by the window, to render target pixels from the bottom left
+    // None outside of the rendered area
+    pub fn window_to_target(&self, pos: V2<f32>) -> Option<[f32;2]> {
+        let size = self.size();
+        let window_size = self.window_size();
+        let [left, bottom, width, height] = self.output.get().unwrap_or([0, 0, window_size[0], window_size[1]]);
+        if width == 0 || height == 0 {
+            return None
+        }
+        let x = (pos.x - left as f32) / width as f32 * size[0] as f32;
+        let y = (window_size[1] as f32 - pos.y - bottom as f32) / height as f32 * size[1] as f32;
+        if x < 0.0 || y < 0.0 || x > size[0] as f32 || y > size[1] as f32 {
+            return None
+        }
+        Some([x, y])
+    }
+    pub fn target_to_window(&self, pos: [f32;2]) -> V2<f32> {
+        let size = self.size();
+        let window_size = self.window_size();
+        let [left, bottom, width, height] = self.output.get().unwrap_or([0, 0, window_size[0], window_size[1]]);
+        let x = left as f32 + pos[0] / size[0].max(1) as f32 * width as f32;
+        let y = bottom as f32 + pos[1] / size[1].max(1) as f32 * height as f32;
+        [x, window_size[1] as f32 - y].into()
+    }
+
+    /// Window pixels from the top left to world units, through the topmost camera under the point
+    pub fn screen_to_world(&self, pos: V2<f32>) -> Option<V2<f32>> {
+        screen_to_world(self, &self.views.borrow(), pos)
+    }
+    /// World units to window pixels from the top left, through the first camera
+    pub fn world_to_screen(&self, pos: V2<f32>) -> Option<V2<f32>> {
+        world_to_screen(self, &self.views.borrow(), pos)
+    }
+}
+
+fn screen_to_world(screen: &Screen, views: &[ViewTransform], pos: V2<f32>) -> Option<V2<f32>> {
+    let target = screen.window_to_target(pos)?;
+    // Later cameras are drawn over earlier ones
+    let view = views.iter().rev().find(|v| v.contains(target)).or(views.first())?;
+    Some(view.to_world(target))
+}
+
+fn world_to_screen(screen: &Screen, views: &[ViewTransform], pos: V2<f32>) -> Option<V2<f32>> {
+    let view = views.first()?;
+    Some(screen.target_to_window(view.to_target(pos)))
+}
+
+impl element::ElementBehavior for Screen {}
+impl element::ModuleBehavior for Screen {
+    fn alias(&self) -> String {
+        "screen".to_string()
+    }
+    fn component(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+
+
+
 #[derive(Clone)]
 pub enum SceneEvent {
+    // Replaces every camera of the scene
     SetCamera(Uuid),
+    // Cameras are drawn in the order they were added
+    AddCamera(Uuid),
+    RemoveCamera(Uuid),
     Instantiate(serde_json::Value),
     Delete(Uuid),
     JSONManager(Weak<JSONManager>)
 }
 
 
-use element::{ModuleTool,Element};
+use element::{ModuleTool,Element,SpriteMode};
 use event::Receiver;
 pub struct Scene {
+    pub id: Uuid,
     pub mod_alias: HashMap<String,Uuid>,
     pub elements: HashMap<Uuid,Element>,
-    camera_uuid: Uuid,
+    // Elements in the order they were added, keeps drawing independent of HashMap order
+    element_order: Vec<Uuid>,
+    cameras: Vec<Uuid>,
+    layers: Vec<render::RenderLayer>,
+    pub screen: Screen,
+    positions: Positions,
+
+    static_geometry: render::StaticGeometry,
+    static_dirty: bool,
+    // Incremented every time the static vertices are rebuilt
+    static_generation: u64,
 
 
     sender: event::Sender<SceneEvent>,
@@ -88,9 +276,18 @@ impl Scene {
     pub fn new() -> Self {
         let s = event::Sender::new();
         let mut scene = Self {
+            id: Uuid::new_v4(),
             mod_alias: HashMap::new(),
             elements: HashMap::new(),
-            camera_uuid: Uuid::new_v4(),
+            element_order: Vec::new(),
+            cameras: Vec::new(),
+            layers: render::default_layers(),
+            screen: Screen::default(),
+            positions: Positions::default(),
+
+            static_geometry: render::StaticGeometry::default(),
+            static_dirty: true,
+            static_generation: 0,
 
             receiver: s.new_receiver(),
             sender: s,
@@ -105,6 +302,12 @@ impl Scene {
         let ut_uuid = scene.add_element(Element::new_module(UuidTags::default()));
         scene.mod_alias.insert("uuid tags".to_string(), ut_uuid);
 
+        let screen_uuid = scene.add_element(Element::new_module(scene.screen.clone()));
+        scene.mod_alias.insert("screen".to_string(), screen_uuid);
+
+        let positions_uuid = scene.add_element(Element::new_module(scene.positions.clone()));
+        scene.mod_alias.insert("positions".to_string(), positions_uuid);
+
 
         scene
     }
@@ -116,7 +319,11 @@ impl Scene {
         if let Element::Module(m) = &element {
             self.mod_alias.insert(m.borrow().alias(), uuid);
         }
+        if element.sprite_mode() == SpriteMode::Static {
+            self.static_dirty = true;
+        }
         self.elements.insert(uuid, element);
+        self.element_order.push(uuid);
         uuid
     }
     pub fn init_elements(&mut self) {
@@ -131,46 +338,202 @@ impl Scene {
     pub fn update_elements(&mut self, td:f32) {
         for e in self.receiver.poll() {
             match e {
-                SceneEvent::SetCamera(uuid) => self.camera_uuid = uuid,
+                SceneEvent::SetCamera(uuid) => self.cameras = vec![uuid],
+                SceneEvent::AddCamera(uuid) => if !self.cameras.contains(&uuid) {
+                    self.cameras.push(uuid);
+                },
+                SceneEvent::RemoveCamera(uuid) => self.cameras.retain(|u| *u != uuid),
                 SceneEvent::Instantiate(value) => {
                     println!("instant");
                     if let Some(json) = self.json_manager.upgrade() {
-                        let uuid = self.add_element(json.create_element(&value));
-                        self.elements.get(&uuid).unwrap().init(uuid, &self.module_tool());
+                        match json.create_element(&value) {
+                            Ok(element) => {
+                                let uuid = self.add_element(element);
+                                self.elements.get(&uuid).unwrap().init(uuid, &self.module_tool());
+                            },
+                            Err(e) => println!("Failed to instantiate: {}", e)
+                        }
                     }
                 },
                 SceneEvent::Delete(uuid) => {
-                    self.elements.remove(&uuid);
+                    self.element_order.retain(|u| *u != uuid);
+                    self.cameras.retain(|u| *u != uuid);
+                    self.positions.set(&uuid, None);
+                    if let Some(e) = self.elements.remove(&uuid) {
+                        if e.sprite_mode() == SpriteMode::Static {
+                            self.static_dirty = true;
+                        }
+                    }
                 },
                 SceneEvent::JSONManager(..) => ()
             }
         }
         //println!("\n--UPDATE ELEMENTS--");
-        for (_uuid, e) in &self.elements {
+        for e in self.elements.values() {
             e.local_update(td);
         }
+        for (uuid, e) in &self.elements {
+            self.positions.set(uuid, e.position());
+        }
         //println!("  -post-");
-        for (_uuid, e) in &self.elements {
+        for e in self.elements.values() {
             e.post_update();
         }
 
+        for e in self.elements.values() {
+            if e.sprite_mode() == SpriteMode::Static && e.dirty() {
+                self.static_dirty = true;
+            }
+        }
+    }
+
+
+    // Draw items of dynamic sprites, rebuilt every frame
+    // Only what's in view of the size given to update_screen is needed, everything before the scene is first drawn
+    pub fn display(&self, sprite_sheet: &sprite::SpriteSheet) -> Vec<render::DrawItem> {
+        let visible = match self.screen.size() {
+            [0, _] | [_, 0] => render::VisibleArea::all(),
+            size => self.visible_area(size)
+        };
+        let mut items = Vec::new();
+        for (order, uuid) in self.element_order.iter().enumerate() {
+            let element = &self.elements[uuid];
+            if element.sprite_mode() == SpriteMode::Static {
+                continue;
+            }
+            items.extend(element.draw_items(sprite_sheet, &self.layers, order, &visible));
+        }
+        items
+    }
+
+    // Geometry of static sprites, only rebuilt when a static element was added, removed or marked dirty
+    pub fn static_display(&mut self, sprite_sheet: &sprite::SpriteSheet) -> &render::StaticGeometry {
+        if self.static_dirty {
+            let mut items = Vec::new();
+            for (order, uuid) in self.element_order.iter().enumerate() {
+                let element = &self.elements[uuid];
+                if element.sprite_mode() != SpriteMode::Static {
+                    continue;
+                }
+                items.extend(element.draw_items(sprite_sheet, &self.layers, order, &render::VisibleArea::all()));
+            }
+            items.sort_by(|a, b| a.draw_order(b));
+
+            let mut geometry = render::StaticGeometry {
+                opaque: vec![Vec::new(); self.layers.len()],
+                translucent: Vec::new()
+            };
+            for item in items {
+                if item.translucent {
+                    geometry.translucent.push(item);
+                } else {
+                    geometry.opaque[item.layer].extend_from_slice(&item.vertices);
+                }
+            }
+            self.static_geometry = geometry;
+            self.static_dirty = false;
+            self.static_generation += 1;
+        }
+        &self.static_geometry
+    }
+    // Static geometry as last built by static_display
+    pub fn static_geometry(&self) -> &render::StaticGeometry {
+        &self.static_geometry
+    }
+    // Changes whenever the static geometry is rebuilt, renderers keep its vertex buffers until then
+    pub fn static_generation(&self) -> u64 {
+        self.static_generation
+    }
+
+    pub fn layers(&self) -> &[render::RenderLayer] {
+        &self.layers
+    }
+    // Static geometry is grouped by layer, so it's rebuilt with the new layers
+    pub fn set_layers(&mut self, layers: Vec<render::RenderLayer>) -> Result<(),String> {
+        if layers.is_empty() {
+            return Err("Scene must have at least one render layer".to_string())
+        }
+        self.layers = layers;
+        self.static_dirty = true;
+        Ok(())
     }
 
 
-    pub fn display(&self, sprite_sheet: &sprite::SpriteSheet) -> Vec<crate::Vertex> {
-        let mut vertices = Vec::new();
-        for (uuid, element) in &self.elements {
-            if let Some(s) = element.sprite() {
-                vertices.append(&mut sprite_sheet.vertices(s));
+    // Cameras with the viewport each one draws to, a full window view without a camera when there are none
+    pub fn views(&self) -> Vec<(Option<Uuid>, render::Viewport)> {
+        let views: Vec<_> = self.cameras.iter()
+            .filter_map(|uuid| self.elements.get(uuid).map(|camera| (Some(*uuid), camera.viewport())))
+            .collect();
+        if views.is_empty() {
+            vec![(None, render::Viewport::default())]
+        } else {
+            views
+        }
+    }
+
+    // World area each layer shows through any camera, on a render target of `size` pixels
+    pub fn visible_area(&self, size: [u32;2]) -> render::VisibleArea {
+        let mut visible = render::VisibleArea::new(self.layers.len());
+        for (camera, viewport) in self.views() {
+            let rect = viewport.pixel_rect(size);
+            if rect[2] == 0 || rect[3] == 0 {
+                continue;
+            }
+            for (idx, layer) in self.layers.iter().enumerate().filter(|(_, l)| viewport.shows(l)) {
+                let (clip_matrix, offset) = self.layer_projection(layer, camera, [rect[2], rect[3]]);
+                let view = ViewTransform { rect, clip_matrix, offset };
+                let a = view.to_world([rect[0] as f32, rect[1] as f32]);
+                let b = view.to_world([(rect[0] + rect[2]) as f32, (rect[1] + rect[3]) as f32]);
+                visible.add(idx, V2 { x: a.x.min(b.x), y: a.y.min(b.y) }, V2 { x: a.x.max(b.x), y: a.y.max(b.y) });
             }
         }
-        vertices
+        visible
+    }
+
+    // Records the render target size and camera transforms used by Screen conversions, called before drawing
+    pub fn update_screen(&self, size: [u32;2]) {
+        self.screen.set_size(size);
+        self.screen.set_views(self.view_transforms(size));
+    }
+
+    pub fn view_transforms(&self, size: [u32;2]) -> Vec<ViewTransform> {
+        self.views().iter().map(|(camera, viewport)| {
+            let rect = viewport.pixel_rect(size);
+            let (clip_matrix, offset) = self.camera_view(*camera, [rect[2], rect[3]]);
+            ViewTransform {
+                rect,
+                clip_matrix,
+                offset
+            }
+        }).collect()
     }
 
+    // Same as Screen::screen_to_world, with the cameras as they are now instead of when last drawn
+    pub fn screen_to_world(&self, pos: V2<f32>) -> Option<V2<f32>> {
+        screen_to_world(&self.screen, &self.view_transforms(self.screen.size()), pos)
+    }
+    pub fn world_to_screen(&self, pos: V2<f32>) -> Option<V2<f32>> {
+        world_to_screen(&self.screen, &self.view_transforms(self.screen.size()), pos)
+    }
+
+    // Projection and translation used to draw a layer, screen space layers ignore the camera
+    // `viewport_size` is the size in pixels of the camera's viewport
+    pub fn layer_projection(&self, layer: &render::RenderLayer, camera: Option<Uuid>, viewport_size: [u32;2]) -> ([[f32;3];3], [f32;2]) {
+        if layer.screen_space {
+            return (render::screen_projection(viewport_size), [viewport_size[0] as f32 * 0.5, viewport_size[1] as f32 * 0.5])
+        }
+        let (ortho_mat, offset) = self.camera_view(camera, viewport_size);
+        (ortho_mat, [offset[0]*layer.parallax.x, offset[1]*layer.parallax.y])
+    }
 
+    // Projection of the first camera
     pub fn camera_projection(&self, window_size: [u32;2]) -> ([[f32;3];3], [f32;2]) {
-        if let Some(camera) = self.elements.get(&self.camera_uuid) {
-            (camera.clip_matrix(window_size), camera.offset())
+        self.camera_view(self.cameras.first().copied(), window_size)
+    }
+
+    fn camera_view(&self, camera: Option<Uuid>, viewport_size: [u32;2]) -> ([[f32;3];3], [f32;2]) {
+        if let Some(camera) = camera.and_then(|uuid| self.elements.get(&uuid)) {
+            (camera.clip_matrix(viewport_size), camera.offset())
         } else {
             ([[1.0,0.0,0.0],[0.0,1.0,0.0],[0.0,0.0,1.0f32]], [0.0,0.0])
         }
@@ -222,10 +585,17 @@ impl SceneManager {
             map_editor: false
         }
     }
-    pub fn create_scene(&mut self, name: String, data: &serde_json::Value) {
-        let mut scene = self.json_manager.create_scene(data);
+    pub fn create_scene(&mut self, name: String, data: &serde_json::Value) -> Result<(),String> {
+        let mut scene = self.json_manager.create_scene(data)?;
         scene.json_manager = std::rc::Rc::downgrade(&self.json_manager);
         self.scenes.insert(name, scene);
+        Ok(())
+    }
+    // Returns warnings about parts of the map that were left out, see tiled::to_scene_json
+    pub fn create_tiled_scene(&mut self, name: String, map: &serde_json::Value) -> Result<Vec<String>,String> {
+        let (data, warnings) = tiled::to_scene_json(map, &self.json_manager)?;
+        self.create_scene(name, &data)?;
+        Ok(warnings)
     }
     pub fn current_scene(&mut self) -> Option<&mut Scene> {
         if let Some(name) = self.name {
@@ -248,47 +618,231 @@ impl SceneManager {
 
 
 pub struct JSONManager {
-    pub element_names: HashMap<String, element::Element>
+    pub element_names: HashMap<String, element::Element>,
+    // Applied to tilemaps with autotiling enabled as they are loaded
+    pub terrains: Vec<core::autotile::Terrain>,
+    // Frame names of the sprite sheet elements are loaded against
+    frames: RefCell<Rc<HashMap<String,usize>>>
 }
 
 impl JSONManager {
     pub fn new() -> Self {
         Self {
-            element_names: HashMap::new()
+            element_names: HashMap::new(),
+            terrains: Vec::new(),
+            frames: RefCell::new(Rc::new(HashMap::new()))
         }
     }
-    pub fn create_element(&self, value: &serde_json::Value) -> Element {
+    /// Resolves frame names in element and terrain data through the sheet's frame table
+    pub fn set_frames(&self, sprite_sheet: &sprite::SpriteSheet) {
+        *self.frames.borrow_mut() = Rc::new(sprite_sheet.frames.clone());
+    }
+    pub fn load_terrains(&mut self, data: &serde_json::Value) -> Result<(),String> {
+        let frames = self.frames.borrow().clone();
+        let mut terrains = sprite::with_frames(&frames, || serde_json::from_value::<Vec<core::autotile::Terrain>>(data.clone())).map_err(|e| format!("Failed to load terrains: {}", e))?;
+        self.terrains.append(&mut terrains);
+        Ok(())
+    }
+    /// Deserializes data that may name sprite frames, such as tilemap settings
+    pub fn from_value<T: serde::de::DeserializeOwned>(&self, value: &serde_json::Value) -> Result<T,String> {
+        let frames = self.frames.borrow().clone();
+        sprite::with_frames(&frames, || serde_json::from_value::<T>(value.clone())).map_err(|e| e.to_string())
+    }
+    pub fn create_element(&self, value: &serde_json::Value) -> Result<Element,String> {
+        let frames = self.frames.borrow().clone();
+        sprite::with_frames(&frames, || self.load_element(value))
+    }
+    fn load_element(&self, value: &serde_json::Value) -> Result<Element,String> {
         use serde_json::*;
-        let mut e = Element::Null;
-        if let Value::Object(element_data) = value {
-            if let Some(Value::String(element_name)) = element_data.get("name") {
-                if let Some(element) = self.element_names.get(element_name) {
-                    e = element.load(&element_data);
-                }
-            }
+        let Value::Object(element_data) = value else {
+            return Err(format!("Element data must be an object, found {}", value))
+        };
+        let Some(Value::String(element_name)) = element_data.get("name") else {
+            return Err("Element data is missing its \"name\"".to_string())
+        };
+        let Some(element) = self.element_names.get(element_name) else {
+            return Err(format!("Element \"{}\" isn't registered", element_name))
+        };
+        match self.autotile(element_data) {
+            Ok(Some(data)) => element.try_load(&data),
+            Ok(None) => element.try_load(element_data),
+            Err(e) => Err(e)
+        }.map_err(|e| format!("Element \"{}\": {}", element_name, e))
+    }
+    // Element data with terrain tiles replaced, when its settings ask for autotiling
+    fn autotile(&self, data: &serde_json::Map<String,serde_json::Value>) -> Result<Option<serde_json::Map<String,serde_json::Value>>,String> {
+        use serde_json::*;
+        let Some(Value::Object(settings)) = data.get("settings") else {
+            return Ok(None)
+        };
+        if self.terrains.is_empty() || settings.get("autotile") != Some(&Value::Bool(true)) {
+            return Ok(None)
         }
-        e
+        let settings = core::tilemap::autotile_settings(settings, &self.terrains).map_err(|e| format!("Failed to autotile: {}", e))?;
+        let mut data = data.clone();
+        data.insert("settings".to_string(), Value::Object(settings));
+        Ok(Some(data))
     }
-    pub fn create_scene(&self, data: &serde_json::Value) -> Scene {
+    // Errors from every element are collected, so a scene reports all of its bad elements at once
+    pub fn create_scene(&self, data: &serde_json::Value) -> Result<Scene,String> {
         use serde_json::*;
         let mut scene = Scene::new();
+        let mut errors = Vec::new();
         if let Value::Object(fields) = data {
+            if let Some(layers) = fields.get("layers") {
+                match serde_json::from_value::<Vec<render::RenderLayer>>(layers.clone()) {
+                    Ok(layers) => if let Err(e) = scene.set_layers(layers) {
+                        errors.push(e)
+                    },
+                    Err(e) => errors.push(format!("Failed to load render layers: {}", e))
+                }
+            }
             if let Some(Value::Array(elements)) = fields.get("elements") {
-                for e in elements {
-                    /*if let Value::Object(element_data) = e {
-                        if let Some(Value::String(element_name)) = element_data.get("name") {
-                            if let Some(element) = self.element_names.get(element_name) {
-                                scene.add_element(element.load(&element_data));
-                            }
-                        }
-                    }*/
+                for (i, e) in elements.iter().enumerate() {
                     match self.create_element(e) {
-                        Element::Null => (),
-                        x => {scene.add_element(x);}
+                        Ok(Element::Null) => (),
+                        Ok(x) => {scene.add_element(x);},
+                        Err(e) => errors.push(format!("elements[{}]: {}", i, e))
                     }
                 }
             }
         }
-        scene
+        if errors.is_empty() {
+            Ok(scene)
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json_manager() -> JSONManager {
+        let mut sheet = sprite::SpriteSheet::new((4,4));
+        sheet.load_frames(&serde_json::json!({"wall": [1, 2]})).unwrap();
+        let mut json = JSONManager::new();
+        json.element_names.insert("block".to_string(), Element::new_gen(core::Block::default()));
+        json.set_frames(&sheet);
+        json
+    }
+
+    // Block data whose sprite uses the given frame
+    fn block(frame: &str) -> serde_json::Value {
+        let mut sprite = serde_json::to_value(sprite::Sprite::single(0)).unwrap();
+        sprite["tex_indices"] = serde_json::json!([[frame]]);
+        serde_json::json!({"name": "block", "settings": {"sprite": sprite}})
+    }
+
+    // Counts how often its sprite is built, dirty once after `changed` is set
+    struct Tracked {
+        mode: SpriteMode,
+        changed: Rc<Cell<bool>>,
+        builds: Rc<Cell<u32>>
+    }
+
+    impl element::ElementBehavior for Tracked {
+        fn sprite(&self) -> Option<sprite::Sprite> {
+            self.builds.set(self.builds.get() + 1);
+            Some(sprite::Sprite::single(0))
+        }
+        fn sprite_mode(&self) -> SpriteMode { self.mode }
+        fn dirty(&mut self) -> bool { self.changed.replace(false) }
+    }
+
+    fn tracked(scene: &mut Scene, mode: SpriteMode) -> (Rc<Cell<bool>>, Rc<Cell<u32>>) {
+        let (changed, builds) = (Rc::new(Cell::new(false)), Rc::new(Cell::new(0)));
+        scene.add_element(Element::new_gen(Tracked { mode: mode, changed: changed.clone(), builds: builds.clone() }));
+        (changed, builds)
+    }
+
+    #[test]
+    fn rebuilds_static_elements_only_when_dirty() {
+        let sheet = sprite::SpriteSheet::new((1,1));
+        let mut scene = Scene::new();
+        let (changed, static_builds) = tracked(&mut scene, SpriteMode::Static);
+
+        scene.static_display(&sheet);
+        let generation = scene.static_generation();
+        assert_eq!(static_builds.get(), 1);
+
+        scene.update_elements(0.1);
+        scene.static_display(&sheet);
+        assert_eq!((static_builds.get(), scene.static_generation()), (1, generation));
+
+        changed.set(true);
+        scene.update_elements(0.1);
+        scene.static_display(&sheet);
+        assert_eq!((static_builds.get(), scene.static_generation()), (2, generation + 1));
+
+        // New layers regroup the static geometry
+        scene.set_layers(render::default_layers()).unwrap();
+        scene.static_display(&sheet);
+        assert_eq!((static_builds.get(), scene.static_generation()), (3, generation + 2));
+        assert!(scene.set_layers(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn splits_static_and_dynamic_sprites() {
+        let sheet = sprite::SpriteSheet::new((1,1));
+        let mut scene = Scene::new();
+        let (_, static_builds) = tracked(&mut scene, SpriteMode::Static);
+        let (_, dynamic_builds) = tracked(&mut scene, SpriteMode::Dynamic);
+
+        let static_vertices: usize = scene.static_display(&sheet).opaque.iter().map(|l| l.len()).sum();
+        assert!(static_vertices > 0);
+        assert_eq!(dynamic_builds.get(), 0);
+
+        for _ in 0..3 {
+            assert_eq!(scene.display(&sheet).len(), 1);
+        }
+        assert_eq!((static_builds.get(), dynamic_builds.get()), (1, 3));
+
+        // Adding a dynamic element leaves the static geometry alone
+        let generation = scene.static_generation();
+        tracked(&mut scene, SpriteMode::Dynamic);
+        scene.static_display(&sheet);
+        assert_eq!(scene.static_generation(), generation);
+    }
+
+    #[test]
+    fn finds_the_area_in_view() {
+        let scene = Scene::new();
+        // Without a camera the world layer shows -1 to 1, screen space layers the render target's pixels from the bottom left
+        let visible = scene.visible_area([32, 18]);
+        assert!(visible.shows(1, [0.5, 0.5].into(), [2.0, 2.0].into()));
+        assert!(!visible.shows(1, [1.5, -0.5].into(), [2.0, 0.5].into()));
+        assert!(visible.shows(3, [30.0, 16.0].into(), [31.0, 17.0].into()));
+        assert!(!visible.shows(3, [-2.0, 0.0].into(), [-1.0, 1.0].into()));
+    }
+
+    #[test]
+    fn creates_elements_with_frame_names() {
+        let element = json_manager().create_element(&block("wall")).unwrap();
+        assert_eq!(element.sprite().unwrap().tex_indices, vec![vec![9]]);
+    }
+
+    #[test]
+    fn names_the_bad_frame_and_element() {
+        let json = json_manager();
+        let err = json.create_element(&block("lava")).err().unwrap();
+        assert!(err.contains("Element \"block\"") && err.contains("\"lava\""), "{}", err);
+        assert!(json.create_element(&serde_json::json!({"name": "door"})).err().unwrap().contains("\"door\""));
+
+        let err = json.create_scene(&serde_json::json!({"elements": [block("wall"), block("lava")]})).err().unwrap();
+        assert!(err.starts_with("elements[1]: ") && !err.contains("elements[0]"), "{}", err);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn returns_autotile_errors() {
+        let mut json = json_manager();
+        json.element_names.insert("tilemap".to_string(), Element::new_gen(core::tilemap::Tilemap::default()));
+        json.terrains.push(core::autotile::Terrain::new("grass", core::autotile::Bitmask::Blob).with_fallback(1));
+
+        let tilemap = |tiles: &str| serde_json::json!({"name": "tilemap", "settings": {"width": 2, "height": 1, "tiles": tiles, "autotile": true}});
+        assert!(json.create_element(&tilemap("1,1")).is_ok());
+        let err = json.create_element(&tilemap("1")).err().unwrap();
+        assert!(err.contains("Element \"tilemap\": Failed to autotile"), "{}", err);
+    }
+}
@@ -4,13 +4,14 @@ out vec4 color;
 uniform sampler2D tex;
 
 in vec2 v_tex_coords;
+in vec4 v_color;
 
 
 
 
 void main() {
 
-    color = texture(tex[0], v_tex_coords);
+    color = texture(tex[0], v_tex_coords) * v_color;
     //color = vec4(0.4,0.25,1.0, color.a);
     
     //color.rgb *= v_brightness;
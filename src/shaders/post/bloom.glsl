@@ -0,0 +1,30 @@
+#version 140
+
+out vec4 color;
+uniform sampler2D scene;
+uniform vec2 resolution;
+uniform float threshold;
+uniform float intensity;
+uniform float radius;
+
+in vec2 v_tex_coords;
+
+
+vec3 bright(vec2 uv) {
+    vec3 c = texture(scene, uv).rgb;
+    float luma = dot(c, vec3(0.2126, 0.7152, 0.0722));
+    return luma > threshold ? c : vec3(0.0);
+}
+
+void main() {
+    color = texture(scene, v_tex_coords);
+
+    vec2 texel = radius / resolution;
+    vec3 glow = vec3(0.0);
+    for (int x = -2; x <= 2; x++) {
+        for (int y = -2; y <= 2; y++) {
+            glow += bright(v_tex_coords + vec2(x, y) * texel);
+        }
+    }
+    color.rgb += glow / 25.0 * intensity;
+}
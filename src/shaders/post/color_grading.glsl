@@ -0,0 +1,22 @@
+#version 140
+
+out vec4 color;
+uniform sampler2D scene;
+uniform float brightness;
+uniform float contrast;
+uniform float saturation;
+uniform vec3 tint;
+
+in vec2 v_tex_coords;
+
+
+void main() {
+    color = texture(scene, v_tex_coords);
+
+    vec3 c = color.rgb + brightness;
+    c = (c - 0.5) * contrast + 0.5;
+    float luma = dot(c, vec3(0.2126, 0.7152, 0.0722));
+    c = mix(vec3(luma), c, saturation);
+
+    color.rgb = clamp(c * tint, 0.0, 1.0);
+}
@@ -0,0 +1,26 @@
+#version 140
+
+out vec4 color;
+uniform sampler2D scene;
+uniform vec2 resolution;
+uniform float curvature;
+uniform float scanlines;
+
+in vec2 v_tex_coords;
+
+
+void main() {
+    // Bend the image outwards from the center
+    vec2 uv = v_tex_coords * 2.0 - 1.0;
+    uv *= 1.0 + curvature * dot(uv.yx, uv.yx);
+    uv = uv * 0.5 + 0.5;
+
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0) {
+        color = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    color = texture(scene, uv);
+    float line = sin(uv.y * resolution.y * 3.14159);
+    color.rgb *= 1.0 - scanlines * (0.5 - 0.5 * line);
+}
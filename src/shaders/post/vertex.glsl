@@ -0,0 +1,11 @@
+#version 140
+
+in vec2 pos;
+
+out vec2 v_tex_coords;
+
+
+void main() {
+    v_tex_coords = pos * 0.5 + 0.5;
+    gl_Position = vec4(pos, 0.0, 1.0);
+}
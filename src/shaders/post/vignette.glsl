@@ -0,0 +1,16 @@
+#version 140
+
+out vec4 color;
+uniform sampler2D scene;
+uniform float strength;
+uniform float radius;
+
+in vec2 v_tex_coords;
+
+
+void main() {
+    color = texture(scene, v_tex_coords);
+
+    float dist = distance(v_tex_coords, vec2(0.5, 0.5));
+    color.rgb *= 1.0 - smoothstep(radius, radius + 0.5, dist) * strength;
+}
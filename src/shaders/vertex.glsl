@@ -2,6 +2,7 @@
 
 in vec3 pos;
 in vec2 tex_coords;
+in vec4 color;
 in float rotation;
 
 uniform mat3 ortho_mat;
@@ -9,10 +10,12 @@ uniform vec2 translation;
 
 
 out vec2 v_tex_coords;
+out vec4 v_color;
 
 
 void main() {
     v_tex_coords = tex_coords;
+    v_color = color;
 
 
 
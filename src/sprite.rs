@@ -1,14 +1,116 @@
 use crate::*;
 use serde::{Deserialize,Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+
+thread_local! {
+    // Frame names of the sheet JSON is currently loaded against, only set inside `with_frames`
+    static FRAME_NAMES: RefCell<Option<Rc<HashMap<String,usize>>>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with frame names resolved against `frames`, used by the JSON manager while loading elements
+pub fn with_frames<R>(frames: &Rc<HashMap<String,usize>>, f: impl FnOnce() -> R) -> R {
+    let _restore = RestoreFrames(FRAME_NAMES.with(|names| names.replace(Some(frames.clone()))));
+    f()
+}
+
+// Puts back the previous frame table, even if loading panics
+struct RestoreFrames(Option<Rc<HashMap<String,usize>>>);
+
+impl Drop for RestoreFrames {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        FRAME_NAMES.with(|names| *names.borrow_mut() = previous);
+    }
+}
+
+/// Looks up a frame name in the table given to `with_frames`
+pub fn frame_index(name: &str) -> Result<usize,String> {
+    FRAME_NAMES.with(|names| match names.borrow().as_ref() {
+        Some(n) => n.get(name).copied().ok_or(format!("Unknown sprite frame \"{}\"", name)),
+        None => Err(format!("Sprite frame \"{}\" can only be named when loaded through a JSONManager", name))
+    })
+}
+
+
+/// A tile on the sprite sheet, referred to either by index or by frame name
+#[derive(Clone,Debug,Serialize,Deserialize)]
+#[serde(untagged)]
+pub enum Frame {
+    Index(usize),
+    Name(String)
+}
+
+impl Frame {
+    pub fn resolve(&self) -> Result<usize,String> {
+        match self {
+            Frame::Index(i) => Ok(*i),
+            Frame::Name(name) => frame_index(name)
+        }
+    }
+}
+
+impl From<usize> for Frame {
+    fn from(value: usize) -> Self { Frame::Index(value) }
+}
+impl From<&str> for Frame {
+    fn from(value: &str) -> Self { Frame::Name(value.to_string()) }
+}
+impl From<String> for Frame {
+    fn from(value: String) -> Self { Frame::Name(value) }
+}
+
+/// Deserializes a single frame given as an index or a name
+pub fn deserialize_frame<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    Frame::deserialize(deserializer)?.resolve().map_err(serde::de::Error::custom)
+}
+
+/// Deserializes a grid of frames given as indices or names
+pub fn deserialize_frames<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec<usize>>, D::Error> {
+    let frames: Vec<Vec<Frame>> = Vec::deserialize(deserializer)?;
+    frames.iter().map(|column| {
+        column.iter().map(|f| f.resolve().map_err(serde::de::Error::custom)).collect()
+    }).collect()
+}
+
+
+/// Stretches a tile over an area while its borders keep their size
+#[derive(Clone,Copy,Debug,Serialize,Deserialize)]
+pub struct NineSlice {
+    // Left, right, bottom and top borders of the source tile, as fractions of the tile
+    pub insets: [f32;4],
+    // Area covered in world units, the sprite's scale is the size the whole tile is drawn at
+    pub size: V2<f32>
+}
+
 
 #[derive(Clone,Serialize,Deserialize,Default)]
 pub struct Sprite {
     pub pos: V2<f32>,
     pub scale: V2<f32>,
     pub depth: f32,
+    #[serde(deserialize_with = "deserialize_frames")]
     pub tex_indices: Vec<Vec<usize>>,
     pub next: Option<Box<Self>>,
-    pub flip: bool
+    pub flip: bool,
+    // Render layer by name, the world layer is used when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layer: Option<String>,
+    // Translucent sprites are drawn back to front after opaque ones
+    #[serde(default)]
+    pub translucent: bool,
+    // Tint multiplied with the texture, white when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<[f32;4]>,
+    // Texture rectangle [left, bottom, right, top] in sheet coordinates from 0 to 1
+    // When set the sprite is a single quad and tex_indices are ignored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uv: Option<[f32;4]>,
+    // Draws the first tile (or the uv rectangle) nine-sliced, flip is ignored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nine_slice: Option<NineSlice>
 }
 
 impl Sprite {
@@ -20,8 +122,16 @@ impl Sprite {
             tex_indices: vec![vec![index]],
             next: None,
             flip: false,
+            layer: None,
+            translucent: false,
+            color: None,
+            uv: None,
+            nine_slice: None
         }
     }
+    pub fn named(sprite_sheet: &SpriteSheet, name: &str) -> Result<Sprite,String> {
+        Ok(Self::single(sprite_sheet.frame(name)?))
+    }
     pub fn composite(start:usize, shape: V2<usize>, ss_width: usize) -> Self {
         let mut ti = Vec::new();
         for c in 0..shape.x {
@@ -36,6 +146,11 @@ impl Sprite {
             tex_indices: ti,
             next: None,
             flip: false,
+            layer: None,
+            translucent: false,
+            color: None,
+            uv: None,
+            nine_slice: None
         }
     }
     pub fn empty() -> Self {
@@ -45,7 +160,12 @@ impl Sprite {
             depth: -1.0,
             tex_indices: Vec::new(),
             next: None,
-            flip: false
+            flip: false,
+            layer: None,
+            translucent: false,
+            color: None,
+            uv: None,
+            nine_slice: None
         }
     }
     pub fn repeat(mut self, times: usize) -> Self {
@@ -55,6 +175,9 @@ impl Sprite {
         }
         self
     }
+    pub fn nine_slice(index: usize, insets: [f32;4], size: V2<f32>) -> Self {
+        Self::single(index).with_nine_slice(insets, size)
+    }
     pub fn with_pos(mut self, pos: V2<f32>) -> Self {
         self.pos = pos;
         self
@@ -67,6 +190,26 @@ impl Sprite {
         self.depth = depth;
         self
     }
+    pub fn with_layer(mut self, layer: &str) -> Self {
+        self.layer = Some(layer.to_string());
+        self
+    }
+    pub fn with_color(mut self, color: [f32;4]) -> Self {
+        self.color = Some(color);
+        self
+    }
+    pub fn with_nine_slice(mut self, insets: [f32;4], size: V2<f32>) -> Self {
+        self.nine_slice = Some(NineSlice { insets, size });
+        self
+    }
+    pub fn with_uv(mut self, uv: [f32;4]) -> Self {
+        self.uv = Some(uv);
+        self
+    }
+    pub fn translucent(mut self, is_translucent: bool) -> Self {
+        self.translucent = is_translucent;
+        self
+    }
     pub fn next(&mut self, sprite: Sprite) {
         if let Some(ref mut this_next) = self.next {
             this_next.next(sprite);
@@ -79,57 +222,157 @@ impl Sprite {
 
 pub struct SpriteSheet {
     pub tex: Option<glium::Texture2d>,
-    pub shape: (usize,usize)
+    pub image: Option<image::RgbaImage>,
+    pub shape: (usize,usize),
+    pub frames: HashMap<String,usize>
 }
 
 impl SpriteSheet {
     pub fn new(shape: (usize,usize)) -> Self {
         Self {
             tex: None,
-            shape: shape
+            image: None,
+            shape: shape,
+            frames: HashMap::new()
         }
     }
-    pub fn vertices(&self, s: Sprite) -> Vec<Vertex> {
+    /// Loads frame names from a JSON object, mapping each name to an index or a [column, row] pair
+    /// Scenes resolve names through `JSONManager::set_frames`, or `Instance::load_frames` which does both
+    pub fn load_frames(&mut self, data: &serde_json::Value) -> Result<(),String> {
+        use serde_json::*;
+        let Value::Object(names) = data else {
+            return Err("Frame table is not an object".to_string())
+        };
+        for (name, value) in names {
+            let index = match value {
+                Value::Number(n) => n.as_u64().map(|i| i as usize),
+                Value::Array(arr) if arr.len() == 2 => {
+                    match (arr[0].as_u64(), arr[1].as_u64()) {
+                        (Some(col), Some(row)) => Some(row as usize * self.shape.0 + col as usize),
+                        _ => None
+                    }
+                },
+                _ => None
+            };
+            match index {
+                Some(i) if i < self.shape.0 * self.shape.1 => { self.frames.insert(name.clone(), i); },
+                Some(i) => return Err(format!("Sprite frame \"{}\" is outside the sheet (index {})", name, i)),
+                None => return Err(format!("Sprite frame \"{}\" must be an index or [column, row]", name))
+            }
+        }
+        Ok(())
+    }
+    pub fn load_frames_file(&mut self, path: &str) -> Result<(),String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        let data = serde_json::from_str(&text).map_err(|e| format!("Could not parse {}: {}", path, e))?;
+        self.load_frames(&data)
+    }
+    pub fn load_image(&mut self, path: &str) -> Result<(),String> {
+        let image = image::open(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
+        self.image = Some(image.to_rgba8());
+        Ok(())
+    }
+    fn nine_slice_vertices(&self, sprite: &Sprite, slice: &NineSlice, color: [f32;4]) -> Vec<Vertex> {
+        let src = match (sprite.uv, sprite.tex_indices.first().and_then(|c| c.first())) {
+            (Some(uv), _) => uv,
+            (None, Some(index)) => self.tile_uv(*index),
+            (None, None) => return Vec::new()
+        };
+        let [left, right, bottom, top] = slice.insets.map(|i| i.clamp(0.0, 1.0));
+
+        // Borders shrink evenly when the area is smaller than both of them
+        let fit = |a: f32, b: f32, size: f32| if a + b > size && a + b > 0.0 { (a*size/(a+b), b*size/(a+b)) } else { (a, b) };
+        let (bl, br) = fit(left*sprite.scale.x, right*sprite.scale.x, slice.size.x);
+        let (bb, bt) = fit(bottom*sprite.scale.y, top*sprite.scale.y, slice.size.y);
+
+        let (su, sv) = (src[2] - src[0], src[3] - src[1]);
+        let us = [src[0], src[0] + left*su, src[2] - right*su, src[2]];
+        let vs = [src[1], src[1] + bottom*sv, src[3] - top*sv, src[3]];
+        let (x, y) = (sprite.pos.x, sprite.pos.y);
+        let xs = [x, x + bl, x + slice.size.x - br, x + slice.size.x];
+        let ys = [y, y + bb, y + slice.size.y - bt, y + slice.size.y];
+
+        let mut vertices = Vec::new();
+        for row in 0..3 {
+            for col in 0..3 {
+                if xs[col+1] <= xs[col] || ys[row+1] <= ys[row] {
+                    continue;
+                }
+                vertices.extend(quad(
+                    [xs[col], ys[row]], [xs[col+1], ys[row+1]],
+                    [us[col], vs[row], us[col+1], vs[row+1]],
+                    sprite.depth, color
+                ));
+            }
+        }
+        vertices
+    }
 
+    // Texture rectangle of a tile, as used by Sprite::uv
+    pub fn tile_uv(&self, index: usize) -> [f32;4] {
+        let unit = (1.0 / self.shape.0 as f32, 1.0 / self.shape.1 as f32);
+        let (col, row) = ((index % self.shape.0) as f32, (index / self.shape.0) as f32);
+        [col*unit.0, row*unit.1, (col+1.0)*unit.0, (row+1.0)*unit.1]
+    }
+    pub fn frame(&self, name: &str) -> Result<usize,String> {
+        self.frames.get(name).copied().ok_or(format!("Unknown sprite frame \"{}\"", name))
+    }
+    pub fn vertices(&self, s: Sprite) -> Vec<Vertex> {
         let mut vertices = Vec::new();
-        let mut current = &Some(Box::new(s.clone()));
+        let mut current = &Some(Box::new(s));
         while let Some(sprite) = current {
-            let tex_unit = (1.0 / self.shape.0 as f32, 1.0 / self.shape.1 as f32);
-        
+            vertices.append(&mut self.sprite_vertices(sprite));
+            current = &sprite.next;
+        }
+        vertices
+    }
+    // Vertices of a single sprite, ignoring any sprites chained after it
+    pub fn sprite_vertices(&self, sprite: &Sprite) -> Vec<Vertex> {
+        let mut vertices = Vec::new();
+        let color = sprite.color.unwrap_or([1.0,1.0,1.0,1.0]);
+
+        if let Some(slice) = &sprite.nine_slice {
+            return self.nine_slice_vertices(sprite, slice, color)
+        }
 
-            for row in 0..sprite.tex_indices.len() {
-                for col in 0..sprite.tex_indices[row].len() {
-                    
-                    let this_idx = sprite.tex_indices[if sprite.flip {sprite.tex_indices.len()-1-row}else{row}][col];
+        if let Some(uv) = sprite.uv {
+            let uv = if sprite.flip { [uv[2], uv[1], uv[0], uv[3]] } else { uv };
+            let end = [sprite.pos.x + sprite.scale.x, sprite.pos.y + sprite.scale.y];
+            return quad([sprite.pos.x, sprite.pos.y], end, uv, sprite.depth, color)
+        }
 
-                    let mut tex_col: V2<f32> = [(this_idx%self.shape.0) as f32 * tex_unit.0, (this_idx%self.shape.0 + 1) as f32 * tex_unit.0].into();
-                    let tex_row: V2<f32> = [(this_idx/self.shape.0) as f32 * tex_unit.1, (this_idx/self.shape.0 + 1) as f32 * tex_unit.1].into();
+        let tex_unit = (1.0 / self.shape.0 as f32, 1.0 / self.shape.1 as f32);
+    
 
-                    if sprite.flip {
-                        let x = tex_col.x;
-                        tex_col.x = tex_col.y;
-                        tex_col.y = x;
+        for row in 0..sprite.tex_indices.len() {
+            for col in 0..sprite.tex_indices[row].len() {
+                
+                let this_idx = sprite.tex_indices[if sprite.flip {sprite.tex_indices.len()-1-row}else{row}][col];
 
+                let mut tex_col: V2<f32> = [(this_idx%self.shape.0) as f32 * tex_unit.0, (this_idx%self.shape.0 + 1) as f32 * tex_unit.0].into();
+                let tex_row: V2<f32> = [(this_idx/self.shape.0) as f32 * tex_unit.1, (this_idx/self.shape.0 + 1) as f32 * tex_unit.1].into();
 
-                        /*let x = tex_row.x;
-                        tex_row.x = tex_row.y;
-                        tex_row.y = x;*/
-                    }
+                if sprite.flip {
+                    let x = tex_col.x;
+                    tex_col.x = tex_col.y;
+                    tex_col.y = x;
 
-                    vertices.append(&mut vec![
-                        Vertex { pos: [sprite.pos.x+sprite.scale.x*row as f32, sprite.pos.y+sprite.scale.y*col as f32, sprite.depth], tex_coords: [tex_col.x, tex_row.x], rotation: 0.0 },
-                        Vertex { pos: [sprite.pos.x+sprite.scale.x*(row+1) as f32, sprite.pos.y+sprite.scale.y*col as f32, sprite.depth], tex_coords: [tex_col.y, tex_row.x], rotation: 0.0 },
-                        Vertex { pos: [sprite.pos.x+sprite.scale.x*(row+1) as f32, sprite.pos.y+sprite.scale.y*(col+1) as f32, sprite.depth], tex_coords: [tex_col.y, tex_row.y], rotation: 0.0 },
 
-                        Vertex { pos: [sprite.pos.x+sprite.scale.x*row as f32, sprite.pos.y+sprite.scale.y*col as f32, sprite.depth], tex_coords: [tex_col.x, tex_row.x], rotation: 0.0 },
-                        Vertex { pos: [sprite.pos.x+sprite.scale.x*row as f32, sprite.pos.y+sprite.scale.y*(col+1) as f32, sprite.depth], tex_coords: [tex_col.x, tex_row.y], rotation: 0.0 },
-                        Vertex { pos: [sprite.pos.x+sprite.scale.x*(row+1) as f32, sprite.pos.y+sprite.scale.y*(col+1) as f32, sprite.depth], tex_coords: [tex_col.y, tex_row.y], rotation: 0.0 },
-                    ]);
+                    /*let x = tex_row.x;
+                    tex_row.x = tex_row.y;
+                    tex_row.y = x;*/
                 }
-            }
 
-            current = &sprite.next;
+                vertices.append(&mut vec![
+                    Vertex { pos: [sprite.pos.x+sprite.scale.x*row as f32, sprite.pos.y+sprite.scale.y*col as f32, sprite.depth], tex_coords: [tex_col.x, tex_row.x], color, rotation: 0.0 },
+                    Vertex { pos: [sprite.pos.x+sprite.scale.x*(row+1) as f32, sprite.pos.y+sprite.scale.y*col as f32, sprite.depth], tex_coords: [tex_col.y, tex_row.x], color, rotation: 0.0 },
+                    Vertex { pos: [sprite.pos.x+sprite.scale.x*(row+1) as f32, sprite.pos.y+sprite.scale.y*(col+1) as f32, sprite.depth], tex_coords: [tex_col.y, tex_row.y], color, rotation: 0.0 },
 
+                    Vertex { pos: [sprite.pos.x+sprite.scale.x*row as f32, sprite.pos.y+sprite.scale.y*col as f32, sprite.depth], tex_coords: [tex_col.x, tex_row.x], color, rotation: 0.0 },
+                    Vertex { pos: [sprite.pos.x+sprite.scale.x*row as f32, sprite.pos.y+sprite.scale.y*(col+1) as f32, sprite.depth], tex_coords: [tex_col.x, tex_row.y], color, rotation: 0.0 },
+                    Vertex { pos: [sprite.pos.x+sprite.scale.x*(row+1) as f32, sprite.pos.y+sprite.scale.y*(col+1) as f32, sprite.depth], tex_coords: [tex_col.y, tex_row.y], color, rotation: 0.0 },
+                ]);
+            }
         }
 
 
@@ -138,4 +381,62 @@ impl SpriteSheet {
 }
 
 
+// Two triangles covering `start` to `end` with the texture rectangle [left, bottom, right, top]
+fn quad(start: [f32;2], end: [f32;2], uv: [f32;4], depth: f32, color: [f32;4]) -> Vec<Vertex> {
+    vec![
+        Vertex { pos: [start[0], start[1], depth], tex_coords: [uv[0], uv[1]], color, rotation: 0.0 },
+        Vertex { pos: [end[0], start[1], depth], tex_coords: [uv[2], uv[1]], color, rotation: 0.0 },
+        Vertex { pos: [end[0], end[1], depth], tex_coords: [uv[2], uv[3]], color, rotation: 0.0 },
+
+        Vertex { pos: [start[0], start[1], depth], tex_coords: [uv[0], uv[1]], color, rotation: 0.0 },
+        Vertex { pos: [start[0], end[1], depth], tex_coords: [uv[0], uv[3]], color, rotation: 0.0 },
+        Vertex { pos: [end[0], end[1], depth], tex_coords: [uv[2], uv[3]], color, rotation: 0.0 },
+    ]
+}
+
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames() -> Rc<HashMap<String,usize>> {
+        let mut sheet = SpriteSheet::new((4,4));
+        sheet.load_frames(&serde_json::json!({"grass": 3, "wall": [1, 2]})).unwrap();
+        Rc::new(sheet.frames)
+    }
+
+    #[test]
+    fn resolves_frame_names() {
+        let frames = frames();
+        assert_eq!(frames.get("wall"), Some(&9));
+        with_frames(&frames, || {
+            assert_eq!(Frame::from("grass").resolve(), Ok(3));
+            assert_eq!(Frame::Index(2).resolve(), Ok(2));
+
+            let mut data = serde_json::to_value(Sprite::single(0)).unwrap();
+            data["tex_indices"] = serde_json::json!([["wall", 1], ["grass"]]);
+            assert_eq!(serde_json::from_value::<Sprite>(data).unwrap().tex_indices, vec![vec![9, 1], vec![3]]);
+        });
+    }
+
+    #[test]
+    fn reports_unknown_frames() {
+        let err = with_frames(&frames(), || frame_index("lava")).unwrap_err();
+        assert!(err.contains("\"lava\""), "{}", err);
+        // Names can't be resolved without a table
+        assert!(frame_index("grass").unwrap_err().contains("JSONManager"));
+        assert!(SpriteSheet::new((2,2)).load_frames(&serde_json::json!({"far": [3, 3]})).is_err());
+    }
+
+    #[test]
+    fn restores_frames_after_panic() {
+        let frames = frames();
+        let outer = with_frames(&frames, || {
+            let panicked = std::panic::catch_unwind(|| with_frames(&Rc::new(HashMap::new()), || panic!("bad data")));
+            assert!(panicked.is_err());
+            frame_index("grass")
+        });
+        assert_eq!(outer, Ok(3));
+        assert!(frame_index("grass").is_err());
+    }
+}
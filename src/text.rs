@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+
+use crate::*;
+
+
+/// Where a glyph is on the sprite sheet and how it is placed, in font units
+#[derive(Clone, Copy, Debug)]
+pub struct Glyph {
+    // Texture rectangle, as used by Sprite::uv
+    pub uv: [f32;4],
+    pub size: V2<f32>,
+    // From the pen position to the top left of the glyph, y grows downwards
+    pub offset: V2<f32>,
+    pub advance: f32
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Align {
+    #[serde(rename = "left")]
+    Left,
+    #[serde(rename = "center")]
+    Center,
+    #[serde(rename = "right")]
+    Right
+}
+
+
+/// How a string is laid out, positions and sizes are in world units
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TextStyle {
+    // Top left of the text, or the anchor of the alignment when there is no wrap width
+    pub pos: V2<f32>,
+    // Height of one line
+    pub size: f32,
+    pub line_spacing: f32,
+    pub align: Align,
+    // Lines are broken between words to fit, and aligned inside of this width
+    pub wrap_width: Option<f32>,
+    pub color: [f32;4],
+    pub depth: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layer: Option<String>,
+    pub translucent: bool
+}
+
+impl std::default::Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            pos: [0.0,0.0].into(),
+            size: 1.0,
+            line_spacing: 1.0,
+            align: Align::Left,
+            wrap_width: None,
+            color: [1.0,1.0,1.0,1.0],
+            depth: 0.0,
+            layer: None,
+            translucent: false
+        }
+    }
+}
+
+impl TextStyle {
+    pub fn new(pos: V2<f32>, size: f32) -> Self {
+        Self {
+            pos,
+            size,
+            ..Default::default()
+        }
+    }
+    pub fn with_align(mut self, align: Align) -> Self {
+        self.align = align;
+        self
+    }
+    pub fn with_wrap(mut self, width: f32) -> Self {
+        self.wrap_width = Some(width);
+        self
+    }
+    pub fn with_color(mut self, color: [f32;4]) -> Self {
+        self.color = color;
+        self
+    }
+    pub fn with_depth(mut self, depth: f32) -> Self {
+        self.depth = depth;
+        self
+    }
+    pub fn with_layer(mut self, layer: &str) -> Self {
+        self.layer = Some(layer.to_string());
+        self
+    }
+}
+
+
+/// Font whose glyphs are regions of the sprite sheet
+#[derive(Clone, Default)]
+pub struct BitmapFont {
+    pub glyphs: HashMap<char, Glyph>,
+    pub kerning: HashMap<(char, char), f32>,
+    pub line_height: f32,
+    // Distance from the top of a line to the baseline
+    pub base: f32
+}
+
+impl BitmapFont {
+    /// Fixed grid font, `chars` are placed on consecutive tiles starting at `first_index`
+    /// Every glyph is one tile, so one font unit is one tile
+    pub fn grid(sprite_sheet: &sprite::SpriteSheet, first_index: usize, chars: &str) -> Self {
+        let mut font = Self {
+            line_height: 1.0,
+            base: 1.0,
+            ..Default::default()
+        };
+        for (idx, c) in chars.chars().enumerate() {
+            font.glyphs.insert(c, Glyph {
+                uv: sprite_sheet.tile_uv(first_index + idx),
+                size: [1.0,1.0].into(),
+                offset: [0.0,0.0].into(),
+                advance: 1.0
+            });
+        }
+        font
+    }
+
+    /// Text (.fnt) font exported by BMFont or compatible tools, font units are pixels
+    /// The font's page has to be part of the sprite sheet image, with its top left at `origin`
+    pub fn load_bmfont(text: &str, sheet_size: [u32;2], origin: [u32;2]) -> Result<Self,String> {
+        let mut font = Self::default();
+        let (w, h) = (sheet_size[0] as f32, sheet_size[1] as f32);
+
+        for line in text.lines() {
+            let mut words = line.splitn(2, ' ');
+            let tag = words.next().unwrap_or("");
+            let values = attributes(words.next().unwrap_or(""));
+            let get = |key: &str| -> Result<f32,String> {
+                values.get(key)
+                    .ok_or(format!("Missing \"{}\" in {} line", key, tag))?
+                    .parse::<f32>().map_err(|_| format!("Invalid \"{}\" in {} line", key, tag))
+            };
+            let get_char = |key: &str| -> Result<char,String> {
+                char::from_u32(get(key)? as u32).ok_or(format!("Invalid character in {} line", tag))
+            };
+
+            match tag {
+                "common" => {
+                    font.line_height = get("lineHeight")?;
+                    font.base = get("base")?;
+                    if get("pages").unwrap_or(1.0) > 1.0 {
+                        return Err("Fonts with more than one page are not supported".to_string())
+                    }
+                },
+                "char" => {
+                    let (x, y) = (get("x")? + origin[0] as f32, get("y")? + origin[1] as f32);
+                    let (gw, gh) = (get("width")?, get("height")?);
+                    // Texture coordinates start at the bottom of the image
+                    font.glyphs.insert(get_char("id")?, Glyph {
+                        uv: [x / w, 1.0 - (y + gh) / h, (x + gw) / w, 1.0 - y / h],
+                        size: [gw, gh].into(),
+                        offset: [get("xoffset")?, get("yoffset")?].into(),
+                        advance: get("xadvance")?
+                    });
+                },
+                "kerning" => {
+                    font.kerning.insert((get_char("first")?, get_char("second")?), get("amount")?);
+                },
+                _ => ()
+            }
+        }
+
+        if font.line_height <= 0.0 {
+            return Err("Font has no common line".to_string())
+        }
+        Ok(font)
+    }
+    pub fn load_bmfont_file(path: &str, sheet_size: [u32;2], origin: [u32;2]) -> Result<Self,String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        Self::load_bmfont(&text, sheet_size, origin).map_err(|e| format!("Could not load {}: {}", path, e))
+    }
+
+    pub fn with_kerning(mut self, first: char, second: char, amount: f32) -> Self {
+        self.kerning.insert((first, second), amount);
+        self
+    }
+
+    // Characters missing from the font are drawn as '?' when it exists
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c).or(self.glyphs.get(&'?').filter(|_| !c.is_whitespace()))
+    }
+    fn advance(&self, c: char, prev: Option<char>) -> f32 {
+        let kerning = prev.and_then(|p| self.kerning.get(&(p, c))).copied().unwrap_or(0.0);
+        let advance = match self.glyph(c) {
+            Some(g) => g.advance,
+            None if c == ' ' => self.line_height * 0.5,
+            None => 0.0
+        };
+        kerning + advance
+    }
+
+    /// Width of a single line in font units
+    pub fn measure(&self, line: &str) -> f32 {
+        let mut prev = None;
+        let mut width = 0.0;
+        for c in line.chars() {
+            width += self.advance(c, prev);
+            prev = Some(c);
+        }
+        width
+    }
+
+    /// Splits text on newlines and, when `max_width` is set, between words so lines fit in it
+    /// Words wider than `max_width` are kept on a line of their own
+    pub fn wrap(&self, text: &str, max_width: Option<f32>) -> Vec<String> {
+        let mut lines = Vec::new();
+        for paragraph in text.split('\n') {
+            let Some(max_width) = max_width else {
+                lines.push(paragraph.to_string());
+                continue;
+            };
+            let mut line = String::new();
+            for word in paragraph.split(' ') {
+                let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+                if !line.is_empty() && self.measure(&candidate) > max_width {
+                    lines.push(std::mem::replace(&mut line, word.to_string()));
+                } else {
+                    line = candidate;
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Size of the laid out text in world units
+    pub fn bounds(&self, text: &str, style: &TextStyle) -> V2<f32> {
+        let scale = style.size / self.line_height;
+        let lines = self.wrap(text, style.wrap_width.map(|w| w / scale));
+        let width = lines.iter().map(|l| self.measure(l)).fold(0.0, f32::max) * scale;
+        let height = if lines.is_empty() { 0.0 } else {
+            style.size + (lines.len() - 1) as f32 * style.size * style.line_spacing
+        };
+        [width, height].into()
+    }
+
+    /// One sprite per glyph, chained after an empty sprite
+    pub fn layout(&self, text: &str, style: &TextStyle) -> sprite::Sprite {
+        let mut sprites = Vec::new();
+        let scale = style.size / self.line_height;
+        let translucent = style.translucent || style.color[3] < 1.0;
+
+        for (row, line) in self.wrap(text, style.wrap_width.map(|w| w / scale)).iter().enumerate() {
+            let width = self.measure(line) * scale;
+            let x = match (style.align, style.wrap_width) {
+                (Align::Left, _) => style.pos.x,
+                (Align::Center, Some(wrap)) => style.pos.x + (wrap - width) * 0.5,
+                (Align::Right, Some(wrap)) => style.pos.x + wrap - width,
+                (Align::Center, None) => style.pos.x - width * 0.5,
+                (Align::Right, None) => style.pos.x - width
+            };
+            let top = style.pos.y - row as f32 * style.size * style.line_spacing;
+
+            let mut pen = 0.0;
+            let mut prev = None;
+            for c in line.chars() {
+                let kerning = prev.and_then(|p| self.kerning.get(&(p, c))).copied().unwrap_or(0.0);
+                if let Some(glyph) = self.glyph(c) {
+                    if glyph.size.x > 0.0 && glyph.size.y > 0.0 {
+                        let mut sprite = sprite::Sprite::empty()
+                            .with_uv(glyph.uv)
+                            .with_color(style.color)
+                            .with_pos([
+                                x + (pen + kerning + glyph.offset.x) * scale,
+                                top - (glyph.offset.y + glyph.size.y) * scale
+                            ].into())
+                            .with_scale([glyph.size.x * scale, glyph.size.y * scale].into())
+                            .with_depth(style.depth)
+                            .translucent(translucent);
+                        if let Some(layer) = &style.layer {
+                            sprite = sprite.with_layer(layer);
+                        }
+                        sprites.push(sprite);
+                    }
+                }
+                pen += self.advance(c, prev);
+                prev = Some(c);
+            }
+        }
+
+        // Linked back to front, appending with Sprite::next walks the whole chain
+        let mut base = sprite::Sprite::empty();
+        for mut sprite in sprites.into_iter().rev() {
+            sprite.next = base.next.take();
+            base.next = Some(Box::new(sprite));
+        }
+        base
+    }
+}
+
+
+// key=value pairs of a BMFont line, values may be quoted
+fn attributes(text: &str) -> HashMap<String, String> {
+    let mut values = HashMap::new();
+    let mut rest = text.trim_start();
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim().to_string();
+        rest = &rest[eq+1..];
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            rest = quoted.get(end+1..).unwrap_or("");
+            quoted[..end].to_string()
+        } else {
+            let end = rest.find(' ').unwrap_or(rest.len());
+            let value = rest[..end].to_string();
+            rest = &rest[end..];
+            value
+        };
+        values.insert(key, value);
+        rest = rest.trim_start();
+    }
+    values
+}
@@ -0,0 +1,390 @@
+use std::cell::RefCell;
+use serde_json::{json, Map, Value};
+
+use crate::*;
+
+
+// Flip flags stored in the top bits of Tiled global tile ids
+const GID_MASK: u64 = 0x1FFFFFFF;
+
+
+/// Reads a map exported from Tiled as JSON (.tmj or .json)
+/// XML maps (.tmx) have to be exported as JSON first
+pub fn load_file(path: &str) -> Result<Value,String> {
+    if path.ends_with(".tmx") {
+        return Err(format!("{} is an XML map, export it from Tiled as JSON (.tmj)", path))
+    }
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    serde_json::from_str(&text).map_err(|e| format!("Could not parse {}: {}", path, e))
+}
+
+
+/// Converts a Tiled map into burge scene JSON, for JSONManager::create_scene
+///
+/// One tile is one world unit and the bottom left of the map is the origin
+/// Tile layers become a "tilemap" element, or one "block" per tile when no tilemap is registered
+/// Objects become the element registered under their type (or class), with their custom properties as settings
+/// Returned along with warnings about what was left out or changed, such as objects of unregistered types
+pub fn to_scene_json(map: &Value, json_manager: &scene::JSONManager) -> Result<(Value, Vec<String>),String> {
+    let map = map.as_object().ok_or("Tiled map is not an object".to_string())?;
+    if map.get("infinite").and_then(Value::as_bool).unwrap_or(false) {
+        return Err("Infinite Tiled maps are not supported".to_string())
+    }
+    let height = get_u64(map, "height")?;
+    let tile_size = [get_u64(map, "tilewidth")? as f32, get_u64(map, "tileheight")? as f32];
+
+    let importer = Importer {
+        json_manager,
+        height,
+        tile_size,
+        tilesets: tilesets(map)?,
+        warnings: RefCell::new(Vec::new())
+    };
+
+    let mut elements = Vec::new();
+    let mut depth = 0.0;
+    if let Some(Value::Array(layers)) = map.get("layers") {
+        importer.layers(layers, &mut depth, &mut elements)?;
+    }
+
+    Ok((json!({ "elements": elements }), importer.warnings.into_inner()))
+}
+
+
+struct Tileset {
+    first_gid: u64,
+    // Index of the tileset's first tile on the sprite sheet
+    offset: u64,
+    properties: Map<String, Value>
+}
+
+fn tilesets(map: &Map<String,Value>) -> Result<Vec<Tileset>,String> {
+    let mut tilesets = Vec::new();
+    if let Some(Value::Array(sets)) = map.get("tilesets") {
+        for set in sets {
+            let set = set.as_object().ok_or("Tileset is not an object".to_string())?;
+            if set.contains_key("source") && !set.contains_key("tiles") && !set.contains_key("tilecount") {
+                return Err("External tilesets are not supported, embed them in the map".to_string())
+            }
+            let props = properties(set.get("properties"));
+            let mut tile_properties = Map::new();
+            if let Some(Value::Array(tiles)) = set.get("tiles") {
+                for tile in tiles {
+                    if let Some(id) = tile.get("id").and_then(Value::as_u64) {
+                        tile_properties.insert(id.to_string(), Value::Object(properties(tile.get("properties"))));
+                    }
+                }
+            }
+            tilesets.push(Tileset {
+                first_gid: get_u64(set, "firstgid")?,
+                offset: props.get("offset").and_then(Value::as_u64).unwrap_or(0),
+                properties: tile_properties
+            });
+        }
+    }
+    tilesets.sort_by_key(|t| t.first_gid);
+    Ok(tilesets)
+}
+
+
+struct Importer<'a> {
+    json_manager: &'a scene::JSONManager,
+    height: u64,
+    tile_size: [f32;2],
+    tilesets: Vec<Tileset>,
+    warnings: RefCell<Vec<String>>
+}
+
+impl<'a> Importer<'a> {
+    fn warn(&self, warning: String) {
+        self.warnings.borrow_mut().push(warning);
+    }
+    // Sprite sheet index and tile properties of a global tile id, flip flags are ignored
+    fn tile(&self, gid: u64) -> Option<(usize, Map<String,Value>)> {
+        let gid = gid & GID_MASK;
+        if gid == 0 {
+            return None
+        }
+        let set = self.tilesets.iter().rev().find(|t| t.first_gid <= gid)?;
+        let local = gid - set.first_gid;
+        let props = set.properties.get(&local.to_string()).and_then(Value::as_object).cloned().unwrap_or_default();
+        Some(((set.offset + local) as usize, props))
+    }
+
+    fn layers(&self, layers: &Vec<Value>, depth: &mut f32, elements: &mut Vec<Value>) -> Result<(),String> {
+        for layer in layers {
+            let layer = layer.as_object().ok_or("Layer is not an object".to_string())?;
+            if !layer.get("visible").and_then(Value::as_bool).unwrap_or(true) {
+                continue;
+            }
+            // Later layers are drawn in front of earlier ones
+            *depth -= 0.001;
+            match layer.get("type").and_then(Value::as_str) {
+                Some("tilelayer") => self.tile_layer(layer, *depth, elements)?,
+                Some("objectgroup") => self.object_layer(layer, elements)?,
+                Some("group") => {
+                    if let Some(Value::Array(children)) = layer.get("layers") {
+                        self.layers(children, depth, elements)?;
+                    }
+                },
+                Some(other) => self.warn(format!("Skipped layer {}: {} layers are not supported", layer_name(layer), other)),
+                None => self.warn(format!("Skipped layer {}: it has no type", layer_name(layer)))
+            }
+        }
+        Ok(())
+    }
+
+    fn tile_layer(&self, layer: &Map<String,Value>, depth: f32, elements: &mut Vec<Value>) -> Result<(),String> {
+        let width = get_u64(layer, "width")? as usize;
+        let height = get_u64(layer, "height")? as usize;
+        if layer.get("encoding").and_then(Value::as_str).unwrap_or("csv") != "csv" {
+            return Err(format!("Tile layer {} must use CSV encoding", layer_name(layer)))
+        }
+        let data: Vec<u64> = match layer.get("data") {
+            Some(Value::Array(data)) => data.iter().map(|v| v.as_u64().unwrap_or(0)).collect(),
+            _ => return Err(format!("Tile layer {} has no data", layer_name(layer)))
+        };
+        let flipped = data.iter().filter(|gid| **gid & !GID_MASK != 0).count();
+        if flipped > 0 {
+            self.warn(format!("Tile layer {} has {} flipped or rotated tiles, they are drawn unflipped", layer_name(layer), flipped));
+        }
+
+        let props = properties(layer.get("properties"));
+        let depth = props.get("depth").and_then(Value::as_f64).map_or(depth, |d| d as f32);
+        let render_layer = props.get("layer").cloned().unwrap_or(Value::Null);
+        let offset = [
+            layer.get("offsetx").and_then(Value::as_f64).unwrap_or(0.0) as f32 / self.tile_size[0],
+            -(layer.get("offsety").and_then(Value::as_f64).unwrap_or(0.0) as f32) / self.tile_size[1]
+        ];
+        // Tile rows of the layer are listed from the top of the map
+        let bottom = self.height as f32 - height as f32 + offset[1];
+
+        if self.json_manager.element_names.contains_key("tilemap") {
+            let mut tiles = Vec::new();
+            let mut tile_properties = Map::new();
+            for gid in &data {
+                match self.tile(*gid) {
+                    Some((index, p)) => {
+                        if !p.is_empty() {
+                            tile_properties.insert(index.to_string(), tile_properties_json(&p));
+                        }
+                        tiles.push(index.to_string());
+                    },
+                    None => tiles.push(".".to_string())
+                }
+            }
+            let mut settings = json!({
+                "pos": [offset[0], bottom],
+                "width": width,
+                "height": height,
+                "tiles": tiles.join(","),
+                "properties": tile_properties,
+                "depth": depth
+            });
+            if !render_layer.is_null() {
+                settings["layer"] = render_layer;
+            }
+            if let Some(autotile) = props.get("autotile") {
+                settings["autotile"] = autotile.clone();
+            }
+            elements.push(json!({ "name": "tilemap", "settings": settings }));
+        } else if self.json_manager.element_names.contains_key("block") {
+            for (i, gid) in data.iter().enumerate() {
+                if let Some((index, p)) = self.tile(*gid) {
+                    let pos = [offset[0] + (i % width) as f32, bottom + (height - 1 - i / width) as f32];
+                    let tile = tile_properties_json(&p);
+                    let mut sprite = json!({
+                        "pos": pos, "scale": [1.0,1.0], "depth": depth,
+                        "tex_indices": [[index]], "next": null, "flip": false
+                    });
+                    if !render_layer.is_null() {
+                        sprite["layer"] = render_layer.clone();
+                    }
+                    elements.push(json!({ "name": "block", "settings": {
+                        "pos": pos,
+                        "shape": [1.0,1.0],
+                        "solid": tile["solid"],
+                        "tags": tile["tags"],
+                        "sprite": sprite
+                    }}));
+                }
+            }
+        } else {
+            self.warn(format!("Skipped tile layer {}: no tilemap or block element is registered", layer_name(layer)));
+        }
+        Ok(())
+    }
+
+    fn object_layer(&self, layer: &Map<String,Value>, elements: &mut Vec<Value>) -> Result<(),String> {
+        let Some(Value::Array(objects)) = layer.get("objects") else {
+            return Ok(())
+        };
+        let map_height = self.height as f32 * self.tile_size[1];
+        for object in objects {
+            let object = object.as_object().ok_or("Object is not an object".to_string())?;
+            let element_type = object.get("type").or(object.get("class")).and_then(Value::as_str).unwrap_or("");
+            if !self.json_manager.element_names.contains_key(element_type) {
+                self.warn(format!("Skipped object {}: no element is registered for type \"{}\"", object.get("name").and_then(Value::as_str).unwrap_or(""), element_type));
+                continue;
+            }
+
+            let x = object.get("x").and_then(Value::as_f64).unwrap_or(0.0) as f32;
+            let y = object.get("y").and_then(Value::as_f64).unwrap_or(0.0) as f32;
+            let w = object.get("width").and_then(Value::as_f64).unwrap_or(0.0) as f32;
+            let h = object.get("height").and_then(Value::as_f64).unwrap_or(0.0) as f32;
+            // Tile objects are anchored at their bottom left, others at their top left
+            let bottom = if object.contains_key("gid") { y } else { y + h };
+            let pos = json!([x / self.tile_size[0], (map_height - bottom) / self.tile_size[1]]);
+            let shape = json!([w / self.tile_size[0], h / self.tile_size[1]]);
+
+            // Custom properties are settings of the element, a "settings" object property is merged with them
+            let mut props = properties(object.get("properties"));
+            let mut settings = match props.remove("settings") {
+                Some(Value::Object(settings)) => settings,
+                _ => Map::new()
+            };
+            for (name, value) in props {
+                settings.entry(name).or_insert(value);
+            }
+            settings.entry("pos").or_insert(pos);
+            settings.entry("shape").or_insert(shape);
+            elements.push(json!({ "name": element_type, "settings": settings }));
+        }
+        Ok(())
+    }
+}
+
+
+// Tiled stores custom properties as [{name, type, value}]
+fn properties(value: Option<&Value>) -> Map<String,Value> {
+    let mut map = Map::new();
+    if let Some(Value::Array(props)) = value {
+        for p in props {
+            if let (Some(Value::String(name)), Some(value)) = (p.get("name"), p.get("value")) {
+                map.insert(name.clone(), value.clone());
+            }
+        }
+    }
+    map
+}
+
+// Maps Tiled tile properties onto core::tilemap::TileProperties, tags are comma separated
+fn tile_properties_json(props: &Map<String,Value>) -> Value {
+    let flag = |name: &str| props.get(name).and_then(Value::as_bool).unwrap_or(false);
+    let tags: Vec<String> = props.get("tags").and_then(Value::as_str)
+        .map(|t| t.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+    json!({
+        "solid": flag("solid"),
+        "one_way": flag("one_way"),
+        "hazard": flag("hazard"),
+        "tags": tags
+    })
+}
+
+fn get_u64(map: &Map<String,Value>, key: &str) -> Result<u64,String> {
+    map.get(key).and_then(Value::as_u64).ok_or(format!("Missing or invalid \"{}\"", key))
+}
+
+fn layer_name(layer: &Map<String,Value>) -> String {
+    layer.get("name").and_then(Value::as_str).unwrap_or("").to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Three by two tiles of 16 pixels, the tileset's tiles start at sheet index 8
+    // The last row has tile 2 flipped horizontally, 0x80000002
+    const MAP: &str = r#"{
+        "width": 3, "height": 2, "tilewidth": 16, "tileheight": 16, "infinite": false,
+        "tilesets": [{
+            "firstgid": 1, "name": "terrain", "tilecount": 4,
+            "properties": [{"name": "offset", "type": "int", "value": 8}],
+            "tiles": [{"id": 1, "properties": [
+                {"name": "solid", "type": "bool", "value": true},
+                {"name": "tags", "type": "string", "value": "ground, grass"}
+            ]}]
+        }],
+        "layers": [
+            {"type": "tilelayer", "name": "ground", "width": 3, "height": 2, "visible": true, "data": [1, 2, 0, 0, 2147483650, 3]},
+            {"type": "group", "name": "things", "visible": true, "layers": [
+                {"type": "objectgroup", "name": "objects", "visible": true, "objects": [
+                    {"name": "crate", "type": "block", "x": 16, "y": 0, "width": 16, "height": 16,
+                        "properties": [{"name": "solid", "type": "bool", "value": true}]},
+                    {"name": "ghost", "type": "spirit", "x": 0, "y": 0, "width": 0, "height": 0}
+                ]},
+                {"type": "imagelayer", "name": "sky", "visible": true}
+            ]},
+            {"type": "tilelayer", "name": "hidden", "width": 3, "height": 2, "visible": false, "data": [1, 1, 1, 1, 1, 1]}
+        ]
+    }"#;
+
+    fn json_manager(elements: &[&str]) -> scene::JSONManager {
+        let mut json = scene::JSONManager::new();
+        for name in elements {
+            let element = match *name {
+                "tilemap" => element::Element::new_gen(core::tilemap::Tilemap::default()),
+                _ => element::Element::new_gen(core::Block::default())
+            };
+            json.element_names.insert(name.to_string(), element);
+        }
+        json
+    }
+
+    #[test]
+    fn converts_layers_objects_and_groups() {
+        let json = json_manager(&["tilemap", "block"]);
+        let (scene, warnings) = to_scene_json(&serde_json::from_str(MAP).unwrap(), &json).unwrap();
+        let elements = scene["elements"].as_array().unwrap();
+        assert_eq!(elements.len(), 2);
+
+        let tilemap = &elements[0]["settings"];
+        assert_eq!(elements[0]["name"], "tilemap");
+        assert_eq!(tilemap["tiles"], "8,9,.,.,9,10");
+        assert_eq!(tilemap["pos"], json!([0.0, 0.0]));
+        assert_eq!(tilemap["properties"]["9"], json!({"solid": true, "one_way": false, "hazard": false, "tags": ["ground", "grass"]}));
+
+        // Objects inside the group, placed from their top left corner in pixels
+        let block = &elements[1];
+        assert_eq!(block["name"], "block");
+        assert_eq!(block["settings"]["solid"], true);
+        assert_eq!(block["settings"]["pos"], json!([1.0, 1.0]));
+        assert_eq!(block["settings"]["shape"], json!([1.0, 1.0]));
+
+        assert_eq!(warnings.len(), 3, "{:?}", warnings);
+        assert!(warnings[0].contains("ground") && warnings[0].contains("1 flipped"));
+        assert!(warnings[1].contains("ghost") && warnings[1].contains("\"spirit\""));
+        assert!(warnings[2].contains("sky"));
+
+        let scene = json.create_scene(&scene).unwrap();
+        assert!(scene.layers().len() > 1);
+    }
+
+    #[test]
+    fn falls_back_to_blocks() {
+        let (scene, _) = to_scene_json(&serde_json::from_str(MAP).unwrap(), &json_manager(&["block"])).unwrap();
+        let tiles: Vec<&Value> = scene["elements"].as_array().unwrap().iter().filter(|e| e["settings"]["sprite"]["tex_indices"].is_array()).collect();
+        // One block per tile, the crate has no sprite
+        assert_eq!(tiles.len(), 4);
+        assert_eq!(tiles[0]["settings"]["sprite"]["tex_indices"], json!([[8]]));
+        assert_eq!(tiles[0]["settings"]["pos"], json!([0.0, 1.0]));
+        assert_eq!(tiles[1]["settings"]["solid"], true);
+
+        let (_, warnings) = to_scene_json(&serde_json::from_str(MAP).unwrap(), &json_manager(&[])).unwrap();
+        assert!(warnings.iter().any(|w| w.contains("Skipped tile layer ground")));
+    }
+
+    #[test]
+    fn rejects_unsupported_maps() {
+        let json = json_manager(&["tilemap"]);
+        let mut map: Value = serde_json::from_str(MAP).unwrap();
+        map["infinite"] = json!(true);
+        assert!(to_scene_json(&map, &json).is_err());
+
+        let mut map: Value = serde_json::from_str(MAP).unwrap();
+        map["layers"][0]["encoding"] = json!("base64");
+        assert!(to_scene_json(&map, &json).unwrap_err().contains("ground"));
+    }
+}
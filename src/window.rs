@@ -0,0 +1,243 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use glium::glutin::{dpi::PhysicalSize, window::{Fullscreen, Icon, WindowBuilder}};
+
+use crate::*;
+use crate::event::{Sender, Receiver};
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowMode {
+    Windowed,
+    // Fullscreen window covering the monitor
+    Borderless,
+    // Changes the monitor's video mode to the one closest to the window size
+    Exclusive
+}
+
+
+/// Settings the window is created with, kept up to date with the requests applied to it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub title: String,
+    // Inner size in pixels, 800 wide with the instance's aspect ratio when not set
+    pub size: Option<[u32;2]>,
+    pub resizable: bool,
+    // Title bar and borders of a windowed window
+    pub decorations: bool,
+    pub mode: WindowMode,
+    pub vsync: bool,
+    pub cursor_visible: bool,
+    // Keeps the cursor inside of the window
+    pub cursor_grab: bool,
+    // Image file used as the window icon
+    pub icon: Option<String>
+}
+
+impl std::default::Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: "burge".to_string(),
+            size: None,
+            resizable: true,
+            decorations: true,
+            mode: WindowMode::Windowed,
+            vsync: true,
+            cursor_visible: true,
+            cursor_grab: false,
+            icon: None
+        }
+    }
+}
+
+impl WindowConfig {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            ..Default::default()
+        }
+    }
+    pub fn with_size(mut self, width: u32, height: u32) -> Self {
+        self.size = Some([width, height]);
+        self
+    }
+    pub fn with_resizable(mut self, resizable: bool) -> Self {
+        self.resizable = resizable;
+        self
+    }
+    pub fn with_decorations(mut self, decorations: bool) -> Self {
+        self.decorations = decorations;
+        self
+    }
+    pub fn with_mode(mut self, mode: WindowMode) -> Self {
+        self.mode = mode;
+        self
+    }
+    pub fn with_vsync(mut self, vsync: bool) -> Self {
+        self.vsync = vsync;
+        self
+    }
+    pub fn with_cursor(mut self, visible: bool, grab: bool) -> Self {
+        self.cursor_visible = visible;
+        self.cursor_grab = grab;
+        self
+    }
+    pub fn with_icon(mut self, path: &str) -> Self {
+        self.icon = Some(path.to_string());
+        self
+    }
+
+    pub(crate) fn builder(&self, size: [u32;2], monitor: &glium::glutin::monitor::MonitorHandle) -> WindowBuilder {
+        let mut wb = WindowBuilder::new()
+            .with_title(self.title.clone())
+            .with_inner_size(PhysicalSize { width: size[0], height: size[1] })
+            .with_resizable(self.resizable)
+            .with_decorations(self.decorations)
+            .with_fullscreen(fullscreen(self.mode, size, monitor));
+        if let Some(path) = &self.icon {
+            match load_icon(path) {
+                Ok(icon) => wb = wb.with_window_icon(Some(icon)),
+                Err(e) => println!("{}", e)
+            }
+        }
+        wb
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub enum WindowRequest {
+    SetTitle(String),
+    SetSize([u32;2]),
+    SetResizable(bool),
+    SetDecorations(bool),
+    SetMode(WindowMode),
+    // Vsync is chosen when the window is created, so this only changes the config, the running window keeps its vsync
+    SetVsync(bool),
+    SetCursorVisible(bool),
+    SetCursorGrab(bool),
+    SetIcon(Option<String>)
+}
+
+
+/// Shared by the instance and every scene as the "window" module
+/// Requests are applied by the instance at the start of the next frame
+#[derive(Clone)]
+pub struct WindowManager {
+    sender: Sender<WindowRequest>,
+    receiver: Rc<Receiver<WindowRequest>>,
+    config: Rc<RefCell<WindowConfig>>
+}
+
+impl std::default::Default for WindowManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WindowManager {
+    pub fn new() -> Self {
+        let sender = Sender::new();
+        Self {
+            receiver: sender.new_receiver(),
+            sender,
+            config: Rc::new(RefCell::new(WindowConfig::default()))
+        }
+    }
+    pub fn request(&self, request: WindowRequest) {
+        self.sender.send(request);
+    }
+    pub fn config(&self) -> WindowConfig {
+        self.config.borrow().clone()
+    }
+    pub fn set_config(&self, config: WindowConfig) {
+        *self.config.borrow_mut() = config;
+    }
+    pub(crate) fn set_size(&self, size: [u32;2]) {
+        self.config.borrow_mut().size = Some(size);
+    }
+
+    // Applies every pending request to the window
+    pub(crate) fn apply(&self, window: &glium::glutin::window::Window) {
+        for request in self.receiver.poll() {
+            let mut config = self.config.borrow_mut();
+            match request {
+                WindowRequest::SetTitle(title) => {
+                    window.set_title(&title);
+                    config.title = title;
+                },
+                WindowRequest::SetSize(size) => {
+                    window.set_inner_size(PhysicalSize { width: size[0], height: size[1] });
+                    config.size = Some(size);
+                },
+                WindowRequest::SetResizable(resizable) => {
+                    window.set_resizable(resizable);
+                    config.resizable = resizable;
+                },
+                WindowRequest::SetDecorations(decorations) => {
+                    window.set_decorations(decorations);
+                    config.decorations = decorations;
+                },
+                WindowRequest::SetMode(mode) => {
+                    let size = window.inner_size();
+                    window.set_fullscreen(fullscreen(mode, config.size.unwrap_or([size.width, size.height]), &window.current_monitor()));
+                    config.mode = mode;
+                },
+                WindowRequest::SetVsync(vsync) => config.vsync = vsync,
+                WindowRequest::SetCursorVisible(visible) => {
+                    window.set_cursor_visible(visible);
+                    config.cursor_visible = visible;
+                },
+                WindowRequest::SetCursorGrab(grab) => match window.set_cursor_grab(grab) {
+                    Ok(()) => config.cursor_grab = grab,
+                    Err(e) => println!("Could not grab the cursor: {}", e)
+                },
+                WindowRequest::SetIcon(path) => match path.as_deref().map(load_icon).transpose() {
+                    Ok(icon) => {
+                        window.set_window_icon(icon);
+                        config.icon = path;
+                    },
+                    Err(e) => println!("{}", e)
+                }
+            }
+        }
+    }
+}
+
+impl element::ModuleBehavior for WindowManager {
+    fn alias(&self) -> String {
+        "window".to_string()
+    }
+    fn component(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+impl element::ElementBehavior for WindowManager {
+    fn load(&self, _data: &serde_json::Map<String,serde_json::Value>) -> element::Element {
+        element::Element::new_module(self.clone())
+    }
+}
+
+
+fn fullscreen(mode: WindowMode, size: [u32;2], monitor: &glium::glutin::monitor::MonitorHandle) -> Option<Fullscreen> {
+    match mode {
+        WindowMode::Windowed => None,
+        WindowMode::Borderless => Some(Fullscreen::Borderless(monitor.clone())),
+        WindowMode::Exclusive => {
+            // Closest size, then the highest refresh rate
+            let distance = |m: &glium::glutin::monitor::VideoMode| (m.size().width as i64 - size[0] as i64).abs() + (m.size().height as i64 - size[1] as i64).abs();
+            monitor.video_modes()
+                .min_by_key(|m| (distance(m), std::cmp::Reverse(m.refresh_rate())))
+                .map(Fullscreen::Exclusive)
+        }
+    }
+}
+
+fn load_icon(path: &str) -> Result<Icon,String> {
+    let image = image::open(path).map_err(|e| format!("Could not open {}: {}", path, e))?.to_rgba8();
+    let (width, height) = image.dimensions();
+    Icon::from_rgba(image.into_raw(), width, height).map_err(|e| format!("Invalid icon {}: {}", path, e))
+}